@@ -1,32 +1,142 @@
 use std::{
     collections::HashMap,
+    mem,
     ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use pokemon_engine::{
-    battle::{Battlefield, NopMessenger},
+    battle::{Battlefield, Messenger},
     party::{Party, PartyId, PartyItem},
 };
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use warp::ws::Message;
 
 use crate::{
-    communication::send_request_error,
-    data::{create_dragon, create_move},
+    data::{
+        create_dragon, create_move, move_data, move_priority, moves::MoveData, server_dragon_data,
+    },
     messages::*,
+    replay::{record_battle, Replay, ReplayParty, SharedReplay},
     room::Room,
     user::User,
 };
 
+use self::{
+    effects::apply_move_effects,
+    field::FieldState,
+    format::BattleFormat,
+    invite::{end_invites_of, BattleSettings},
+    messenger::RoomNotifierMessenger,
+    preview::{cancel_team_preview, TeamPreview},
+    state::{BattleOutcome, BattleState, PartyState, SharedBattleState},
+    timer::{TimerCommand, TimerSettings},
+};
+
+pub mod damage;
+pub mod effects;
+pub mod field;
+pub mod format;
+pub mod invite;
 pub mod messenger;
+pub mod preview;
+pub mod ruleset;
+pub mod stages;
+pub mod state;
+pub mod status;
+pub mod timer;
 
-pub type ServerMessenger = NopMessenger;
+pub type ServerMessenger = RoomNotifierMessenger;
 
 pub struct Battle {
     pub usernames: (String, String),
-    pub prepared_action: Option<(PartyId, BattleAction)>,
+    pub format: BattleFormat,
+    pub phase: BattlePhase,
+    /// The actions chosen for the next turn so far, with the slot of the
+    /// dragon using them
+    pub chosen_actions: Vec<(PartyId, u8, BattleAction)>,
     pub battlefield: Battlefield<ServerMessenger>,
+    pub state: SharedBattleState,
+    pub field: FieldState,
+    pub turns: u32,
+    /// Increased every time the server starts waiting for actions, to tell
+    /// whether a timeout is still relevant
+    pub decision: u32,
+    pub timer_settings: TimerSettings,
+    /// How many times each party ran out of time
+    pub timeouts: (u8, u8),
+    pub replay: SharedReplay,
+    /// The party that offered a draw this turn, if any
+    pub draw_offer: Option<PartyId>,
+    /// The parties whose users are disconnected. The turn timer does not run
+    /// while this is not empty.
+    pub disconnected: Vec<PartyId>,
+    pub rng: StdRng,
+    /// A handle to the messenger owned by the battlefield, for events that
+    /// happen outside of it
+    pub notifier: ServerMessenger,
 }
 
 impl Battle {
+    /// Sets up a battle between two parties, which should have been checked
+    /// with [`validate_party`] and [`BattleFormat::check_party`]. Battle
+    /// events are sent to `events`.
+    pub fn new(
+        usernames: (String, String),
+        parties: (&[PartySlot], &[PartySlot]),
+        format: BattleFormat,
+        events: UnboundedSender<Message>,
+        replay_id: String,
+        seed: u64,
+    ) -> Option<Self> {
+        let slots = format.active_slots();
+        let state = Arc::new(Mutex::new(BattleState::new(
+            PartyState::new(parties.0, slots)?,
+            PartyState::new(parties.1, slots)?,
+        )));
+        let replay = Arc::new(Mutex::new(Replay::new(
+            replay_id,
+            seed,
+            format,
+            vec![
+                ReplayParty {
+                    username: usernames.0.clone(),
+                    party: parties.0.to_vec(),
+                },
+                ReplayParty {
+                    username: usernames.1.clone(),
+                    party: parties.1.to_vec(),
+                },
+            ],
+        )));
+        let notifier = RoomNotifierMessenger::new(events, state.clone());
+
+        Some(Self {
+            battlefield: Battlefield::new(
+                engine_party(parties.0)?,
+                engine_party(parties.1)?,
+                notifier.clone(),
+            ),
+            format,
+            phase: BattlePhase::ChoosingActions,
+            chosen_actions: vec![],
+            turns: 0,
+            decision: 0,
+            timer_settings: TimerSettings::default(),
+            timeouts: (0, 0),
+            draw_offer: None,
+            disconnected: vec![],
+            rng: StdRng::seed_from_u64(seed),
+            replay,
+            usernames,
+            state,
+            field: FieldState::default(),
+            notifier,
+        })
+    }
+
     pub fn user_party_id(&self, username: &str) -> Option<PartyId> {
         if self.usernames.0 == username {
             Some(PartyId::Party1)
@@ -43,51 +153,382 @@ impl Battle {
             PartyId::Party2 => &self.usernames.1,
         }
     }
+
+    /// Points the events of `party_id` at the dragon in `slot`
+    pub fn focus(&self, party_id: PartyId, slot: usize) {
+        self.state.lock().unwrap().party_mut(party_id).focus = slot;
+    }
+
+    fn slot_fainted(&self, party_id: PartyId, slot: u8) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .party(party_id)
+            .in_slot(slot as usize)
+            .fainted()
+    }
+
+    fn has_chosen(&self, party_id: PartyId, slot: u8) -> bool {
+        self.chosen_actions
+            .iter()
+            .any(|(p, s, _)| *p == party_id && *s == slot)
+    }
+
+    /// Whether another dragon of `party_id` is already switching to `idx`
+    /// this turn
+    fn switching_to(&self, party_id: PartyId, idx: u8) -> bool {
+        self.chosen_actions
+            .iter()
+            .any(|(p, _, action)| *p == party_id && *action == BattleAction::Switch(idx))
+    }
+
+    /// The action the dragon in `slot` of `party_id` is forced to take this
+    /// turn, if it is locked into a move
+    pub fn locked_action(&self, party_id: PartyId, slot: u8) -> Option<BattleAction> {
+        let state = self.state.lock().unwrap();
+        let charging = state
+            .party(party_id)
+            .in_slot(slot as usize)
+            .charging
+            .clone()?;
+        Some(BattleAction::UseMove(charging, None))
+    }
+
+    /// The slots of `party_id` the server is waiting for an action for
+    pub fn waiting_slots(&self, party_id: PartyId) -> Vec<u8> {
+        match &self.phase {
+            BattlePhase::ForcedSwitch(slots) => slots
+                .iter()
+                .filter(|(p, _)| *p == party_id)
+                .map(|(_, slot)| *slot)
+                .collect(),
+            BattlePhase::ChoosingActions => (0..self.format.active_slots() as u8)
+                .filter(|slot| {
+                    !self.slot_fainted(party_id, *slot)
+                        && !self.has_chosen(party_id, *slot)
+                        && self.locked_action(party_id, *slot).is_none()
+                })
+                .collect(),
+        }
+    }
+
+    /// The parties the server is waiting for to choose an action
+    pub fn waiting_parties(&self) -> Vec<PartyId> {
+        [PartyId::Party1, PartyId::Party2]
+            .iter()
+            .copied()
+            .filter(|party_id| !self.waiting_slots(*party_id).is_empty())
+            .collect()
+    }
+
+    pub fn waiting_usernames(&self) -> Vec<String> {
+        self.waiting_parties()
+            .into_iter()
+            .map(|party_id| self.party_id_user(party_id).to_owned())
+            .collect()
+    }
+
+    /// Every action the dragon in `slot` of `party_id` may choose right now.
+    /// In doubles, the moves hitting a single opponent are listed once for
+    /// every dragon they can target.
+    pub fn legal_actions(&self, party_id: PartyId, slot: u8) -> Vec<BattleAction> {
+        let state = self.state.lock().unwrap();
+        let party = state.party(party_id);
+        let switches = party
+            .switch_targets()
+            .into_iter()
+            .filter(|idx| !self.switching_to(party_id, *idx))
+            .map(BattleAction::Switch);
+        match self.phase {
+            BattlePhase::ForcedSwitch(_) => switches.collect(),
+            BattlePhase::ChoosingActions => {
+                let opponent = state.party(party_id.opposing());
+                let targets: Vec<u8> = (0..opponent.active.len())
+                    .filter(|target| !opponent.in_slot(*target).fainted())
+                    .map(|target| target as u8)
+                    .collect();
+                party
+                    .in_slot(slot as usize)
+                    .moves
+                    .iter()
+                    .flat_map(|move_name| {
+                        let targeted = self.format == BattleFormat::Doubles
+                            && move_data(move_name).is_some_and(|m| m.targets_enemy() && !m.spread);
+                        if targeted {
+                            targets
+                                .iter()
+                                .map(|target| {
+                                    BattleAction::UseMove(move_name.clone(), Some(*target))
+                                })
+                                .collect()
+                        } else {
+                            vec![BattleAction::UseMove(move_name.clone(), None)]
+                        }
+                    })
+                    .chain(switches)
+                    .collect()
+            }
+        }
+    }
+
+    /// The action chosen for a dragon whose user ran out of time
+    pub fn default_action(&self, party_id: PartyId, slot: u8) -> BattleAction {
+        self.legal_actions(party_id, slot).remove(0)
+    }
+
+    /// The state of the battle as seen by the user of `viewer`, or by a
+    /// spectator
+    pub fn snapshot(&self, viewer: Option<PartyId>) -> BattleStateReply {
+        let waiting = self.waiting_parties();
+        let state = self.state.lock().unwrap();
+        let party_snapshot = |party_id: PartyId| {
+            state.party(party_id).snapshot(
+                self.party_id_user(party_id),
+                viewer == Some(party_id),
+                self.field.hazards(party_id),
+                waiting.contains(&party_id),
+            )
+        };
+        BattleStateReply {
+            format: self.format.name().to_owned(),
+            turn: self.turns + 1,
+            party: viewer.map(Into::into),
+            parties: vec![
+                party_snapshot(PartyId::Party1),
+                party_snapshot(PartyId::Party2),
+            ],
+            weather: self
+                .field
+                .weather
+                .as_ref()
+                .map(|w| w.weather.name().to_owned()),
+            weather_turns_left: self.field.weather.as_ref().map_or(0, |w| w.turns_left),
+        }
+    }
+
+    pub fn timeouts_mut(&mut self, party_id: PartyId) -> &mut u8 {
+        match party_id {
+            PartyId::Party1 => &mut self.timeouts.0,
+            PartyId::Party2 => &mut self.timeouts.1,
+        }
+    }
+
+    pub fn active_hp(&self, party_id: PartyId) -> u32 {
+        self.state.lock().unwrap().party(party_id).active().hp
+    }
+
+    pub fn active_fainted(&self, party_id: PartyId) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .party(party_id)
+            .active()
+            .fainted()
+    }
+
+    pub fn end_notify(&self, outcome: BattleOutcome, reason: &str) -> BattleEndNotify {
+        let state = self.state.lock().unwrap();
+        let (winner, loser) = match outcome {
+            BattleOutcome::Win(party_id) => (
+                Some(self.party_id_user(party_id).to_owned()),
+                Some(self.party_id_user(party_id.opposing()).to_owned()),
+            ),
+            BattleOutcome::Draw => (None, None),
+        };
+        BattleEndNotify {
+            winner,
+            loser,
+            reason: reason.to_owned(),
+            replay_id: self.replay.lock().unwrap().id.clone(),
+            summary: BattleSummary {
+                turns: self.turns,
+                parties: vec![
+                    state.party(PartyId::Party1).summary(&self.usernames.0),
+                    state.party(PartyId::Party2).summary(&self.usernames.1),
+                ],
+            },
+        }
+    }
+}
+
+fn engine_party(slots: &[PartySlot]) -> Option<Party> {
+    Some(Party::new_from_vec(
+        slots
+            .iter()
+            .map(|slot| create_dragon(&slot.dragon).map(PartyItem::new))
+            .collect::<Option<_>>()?,
+    ))
+}
+
+/// Where a battle stands once every action that could be played was played
+pub enum BattleProgress {
+    Over(BattleOutcome),
+    /// The battle waits for actions. The listed slots have just been asked to
+    /// replace their fainted dragon.
+    Waiting(Vec<(PartyId, u8)>),
+}
+
+pub enum BattlePhase {
+    /// Both parties are choosing their actions for the next turn
+    ChoosingActions,
+    /// The fainted dragons in the listed slots have to be replaced before
+    /// the next turn can begin
+    ForcedSwitch(Vec<(PartyId, u8)>),
 }
 
 pub enum RoomBattleStatus {
     None,
-    Prepared {
-        starter_username: String,
-        starter_party: Vec<String>,
-        other_username: String,
-    },
-    Started(Battle),
+    /// The users of an accepted invitation are choosing their dragons
+    Preview(TeamPreview),
+    Started(Box<Battle>),
 }
 
 impl RoomBattleStatus {
     pub fn unwrap(self) -> Battle {
         match self {
-            Self::None | Self::Prepared { .. } => panic!("Unwrapping uninitialized battle"),
-            Self::Started(battle) => battle,
+            Self::None | Self::Preview(_) => panic!("Unwrapping uninitialized battle"),
+            Self::Started(battle) => *battle,
         }
     }
 
     pub fn unwrap_ref(&self) -> &Battle {
         match self {
-            Self::None | Self::Prepared { .. } => panic!("Unwrapping uninitialized battle"),
+            Self::None | Self::Preview(_) => panic!("Unwrapping uninitialized battle"),
             Self::Started(battle) => battle,
         }
     }
 
     pub fn unwrap_ref_mut(&mut self) -> &mut Battle {
         match self {
-            Self::None | Self::Prepared { .. } => panic!("Unwrapping uninitialized battle"),
+            Self::None | Self::Preview(_) => panic!("Unwrapping uninitialized battle"),
             Self::Started(battle) => battle,
         }
     }
 }
 
+#[derive(Clone, PartialEq)]
 pub enum BattleAction {
-    UseMove(String),
+    /// A move, and the opposing slot it is aimed at in doubles. Moves are
+    /// aimed at the dragon across the field by default.
+    UseMove(String, Option<u8>),
     Switch(u8),
 }
 
-pub async fn handle_battle_request<U, R>(
-    BattleStartRequest {
-        dragon_names,
-        other_user,
-    }: BattleStartRequest,
+pub const MAX_PARTY_SIZE: usize = 6;
+pub const MAX_MOVES: usize = 4;
+/// Dragons without a level are at this level
+pub const MAX_LEVEL: u8 = 100;
+const REPLAY_ID_LENGTH: usize = 8;
+
+/// Checks a party sent by a client, returning the request error if it can not
+/// be used in a battle.
+pub fn validate_party(party: &[PartySlot]) -> Result<(), &'static str> {
+    if party.is_empty() {
+        return Err("empty_party");
+    }
+    if party.len() > MAX_PARTY_SIZE {
+        return Err("too_many_party_items");
+    }
+    for PartySlot {
+        dragon,
+        moves,
+        level,
+    } in party.iter()
+    {
+        let learnset = &server_dragon_data(dragon)
+            .ok_or("invalid_party_item")?
+            .moves;
+        if matches!(level, Some(level) if *level == 0 || *level > MAX_LEVEL) {
+            return Err("invalid_level");
+        }
+        if moves.is_empty() {
+            return Err("no_moves");
+        }
+        if moves.len() > MAX_MOVES {
+            return Err("too_many_moves");
+        }
+        for (idx, move_name) in moves.iter().enumerate() {
+            if move_data(move_name).is_none() {
+                return Err("invalid_move_name");
+            }
+            if !learnset.contains(move_name) {
+                return Err("move_not_learnable");
+            }
+            if moves[..idx].contains(move_name) {
+                return Err("duplicate_move");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The dragons of a party, as shown to the opponent before the battle
+pub fn roster(party: &[PartySlot]) -> Vec<String> {
+    party.iter().map(|slot| slot.dragon.clone()).collect()
+}
+
+/// Starts a battle between two users. Each user is told the `roster` of the
+/// other one, which is their whole party even if they brought only some of
+/// their dragons after a team preview, so it does not tell which ones.
+pub fn begin_battle<U>(
+    room: &mut Room,
+    users: &U,
+    usernames: (String, String),
+    parties: (Vec<PartySlot>, Vec<PartySlot>),
+    rosters: (Vec<String>, Vec<String>),
+    settings: &BattleSettings,
+) -> Result<(), &'static str>
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let replay_id = rand::thread_rng()
+        .sample_iter(crate::UppercaseAlphanumericDistribution::new())
+        .take(REPLAY_ID_LENGTH)
+        .collect();
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let format = settings.format;
+    let mut battle = Battle::new(
+        usernames.clone(),
+        (&parties.0, &parties.1),
+        format,
+        events_tx,
+        replay_id,
+        rand::random(),
+    )
+    .ok_or("invalid_party_item")?;
+    battle.timer_settings = settings.ruleset.timer;
+    // Battle events go through the replay recorder before reaching the room
+    tokio::spawn(record_battle(
+        events_rx,
+        room.tx.clone(),
+        battle.replay.clone(),
+    ));
+
+    users[&usernames.0]
+        .send(BattleStartNotify {
+            other_party: rosters.1,
+            format: format.name().to_owned(),
+        })
+        .unwrap();
+    users[&usernames.1]
+        .send(BattleStartNotify {
+            other_party: rosters.0,
+            format: format.name().to_owned(),
+        })
+        .unwrap();
+
+    let snapshot = battle.snapshot(None).to_message();
+    room.battle = RoomBattleStatus::Started(Box::new(battle));
+    for spectator in room.spectators() {
+        users[spectator].send_raw(snapshot.clone()).unwrap();
+    }
+    room.send_info();
+    start_turn(room);
+    Ok(())
+}
+
+pub async fn handle_in_battle_request<U, R>(
+    req: WsMessage,
     users: U,
     mut rooms: R,
     source_username: &str,
@@ -95,112 +536,85 @@ pub async fn handle_battle_request<U, R>(
     U: DerefMut + Deref<Target = HashMap<String, User>>,
     R: DerefMut + Deref<Target = HashMap<String, Room>>,
 {
-    let source_user = users.get(source_username).unwrap();
+    let source_user = &users[source_username];
     let room_id = match &source_user.current_room_id {
-        Some(room_id) => room_id,
+        Some(id) => id,
         None => {
-            send_request_error(&source_user.tx, "no_battle_in_main_room").unwrap();
+            source_user
+                .send_request_error("no_battle_in_main_room")
+                .unwrap();
             return;
         }
     };
-    let mut room = rooms.get_mut(room_id).unwrap();
-    if !room.users.contains(&other_user) {
-        send_request_error(&source_user.tx, "battle_opponent_not_found").unwrap();
+    let room = rooms.get_mut(room_id).unwrap();
+    let battle = match &mut room.battle {
+        RoomBattleStatus::None | RoomBattleStatus::Preview(_) => {
+            source_user
+                .send_request_error("no_battle_initiated")
+                .unwrap();
+            return;
+        }
+        RoomBattleStatus::Started(battle) => battle,
+    };
+    let source_party_id = if let Some(id) = battle.user_party_id(&source_username) {
+        id
+    } else {
+        source_user.send_request_error("not_in_battle").unwrap();
         return;
-    }
+    };
+    // let source_party = battle.battlefield.party_mut(source_party_id);
 
-    let other_user = users.get(&other_user).unwrap();
+    let (slot, battle_action) = match req {
+        WsMessage::UseMoveRequest(req) => {
+            (req.slot, BattleAction::UseMove(req.move_name, req.target))
+        }
+        WsMessage::SwitchRequest(req) => (req.slot, BattleAction::Switch(req.next_dragon)),
+        _ => unreachable!(),
+    };
+    // Without a slot, the action is for the first dragon waiting for one
+    let slot = slot
+        .or_else(|| battle.waiting_slots(source_party_id).first().copied())
+        .unwrap_or(0);
 
-    if dragon_names.len() > 6 {
-        send_request_error(&source_user.tx, "too_many_party_items").unwrap();
-        return;
+    match submit_action(battle, source_party_id, slot, battle_action) {
+        Ok(true) => advance_battle(room, &users),
+        Ok(false) => {}
+        Err(reason) => source_user.send_request_error(reason).unwrap(),
     }
+}
 
-    room.battle = match &room.battle {
-        RoomBattleStatus::None => {
-            other_user
-                .send(BattleInvitation {
-                    other_user: source_user.name.clone(),
-                })
+pub async fn handle_battle_state_request<U, R>(users: U, rooms: R, source_username: &str)
+where
+    U: Deref<Target = HashMap<String, User>>,
+    R: Deref<Target = HashMap<String, Room>>,
+{
+    let source_user = &users[source_username];
+    let room_id = match &source_user.current_room_id {
+        Some(id) => id,
+        None => {
+            source_user
+                .send_request_error("no_battle_in_main_room")
                 .unwrap();
-            RoomBattleStatus::Prepared {
-                starter_username: String::from(source_username),
-                starter_party: dragon_names,
-                other_username: other_user.name.clone(),
-            }
+            return;
         }
-        RoomBattleStatus::Prepared {
-            starter_username,
-            other_username,
-            starter_party: starter_dragon_names,
-        } => {
-            if &source_user.name != other_username || &other_user.name != starter_username {
-                send_request_error(&source_user.tx, "another_battle_already_prepared").unwrap();
-            }
-
-            // The user that started the battle invite
-            let starter_user = users.get(starter_username).unwrap();
-
-            // The party we're going to give to the starter user
-            let mut starter_party = vec![];
-
-            for d in starter_dragon_names.iter() {
-                starter_party.push(PartyItem::new(match create_dragon(d) {
-                    Some(d) => d,
-                    None => {
-                        send_request_error(
-                            &source_user.tx,
-                            "invalid_party_item_in_requester_party",
-                        )
-                        .unwrap();
-                        send_request_error(&starter_user.tx, "invalid_party_item").unwrap();
-                        return;
-                    }
-                }));
-            }
-
-            // The party we'll give to the other user
-            let mut other_party = vec![];
-            for d in dragon_names.iter() {
-                other_party.push(PartyItem::new(match create_dragon(d) {
-                    Some(d) => d,
-                    None => {
-                        send_request_error(&source_user.tx, "invalid_party_item").unwrap();
-                        return;
-                    }
-                }));
-            }
-
-            starter_user
-                .send(BattleStartNotify {
-                    other_party: dragon_names,
-                })
-                .unwrap();
-
+    };
+    let battle = match &rooms[room_id].battle {
+        RoomBattleStatus::Started(battle) => battle,
+        _ => {
             source_user
-                .send(BattleStartNotify {
-                    other_party: starter_dragon_names.clone(),
-                })
+                .send_request_error("no_battle_initiated")
                 .unwrap();
-
-            RoomBattleStatus::Started(Battle {
-                battlefield: Battlefield::new(
-                    Party::new_from_vec(starter_party),
-                    Party::new_from_vec(other_party),
-                    NopMessenger,
-                ),
-                prepared_action: None,
-                usernames: (starter_username.clone(), other_username.clone()),
-            })
-        }
-        &RoomBattleStatus::Started(_) => {
-            send_request_error(&source_user.tx, "ongoing_battle").unwrap();
             return;
         }
-    }
+    };
+    source_user
+        .send(battle.snapshot(battle.user_party_id(source_username)))
+        .unwrap();
 }
 
-pub async fn handle_in_battle_request<U, R>(
+/// Handles the messages that end a battle without playing it: forfeiting and
+/// agreeing to a draw.
+pub async fn handle_battle_end_request<U, R>(
     req: WsMessage,
     users: U,
     mut rooms: R,
@@ -221,61 +635,752 @@ pub async fn handle_in_battle_request<U, R>(
     };
     let room = rooms.get_mut(room_id).unwrap();
     let battle = match &mut room.battle {
-        RoomBattleStatus::None | RoomBattleStatus::Prepared { .. } => {
+        RoomBattleStatus::Started(battle) => battle,
+        _ => {
             source_user
                 .send_request_error("no_battle_initiated")
                 .unwrap();
             return;
         }
+    };
+    let source_party_id = match battle.user_party_id(source_username) {
+        Some(id) => id,
+        None => {
+            source_user.send_request_error("not_in_battle").unwrap();
+            return;
+        }
+    };
+
+    match req {
+        WsMessage::ForfeitRequest(_) => {
+            end_battle(
+                room,
+                BattleOutcome::Win(source_party_id.opposing()),
+                "forfeit",
+            );
+        }
+        // Offering a draw when the opponent already did is the same as
+        // accepting theirs
+        WsMessage::OfferDrawRequest(_) if battle.draw_offer != Some(source_party_id.opposing()) => {
+            if battle.draw_offer == Some(source_party_id) {
+                source_user
+                    .send_request_error("draw_already_offered")
+                    .unwrap();
+                return;
+            }
+            battle.draw_offer = Some(source_party_id);
+            battle.notifier.send(DrawOfferNotify {
+                username: source_username.to_owned(),
+            });
+        }
+        WsMessage::OfferDrawRequest(_) | WsMessage::AcceptDrawRequest(_) => {
+            if battle.draw_offer != Some(source_party_id.opposing()) {
+                source_user.send_request_error("no_draw_offer").unwrap();
+                return;
+            }
+            end_battle(room, BattleOutcome::Draw, "draw_agreed");
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Pauses the battle of a user who lost their connection, and returns how long
+/// they have to reconnect, if they are in a battle.
+pub fn handle_user_disconnected(room: &mut Room, username: &str) -> Option<u64> {
+    let battle = match &mut room.battle {
         RoomBattleStatus::Started(battle) => battle,
+        _ => return None,
     };
-    let source_party_id = if let Some(id) = battle.user_party_id(&source_username) {
-        id
+    let party_id = battle.user_party_id(username)?;
+    battle.disconnected.push(party_id);
+    // Invalidates a timeout that might be waiting for the lock
+    battle.decision += 1;
+    let reconnect_seconds = battle.timer_settings.reconnect_seconds;
+    room.timer.send(TimerCommand::Stop).unwrap();
+    battle.notifier.send(OpponentDisconnectedNotify {
+        username: username.to_owned(),
+        reconnect_seconds,
+    });
+    Some(reconnect_seconds)
+}
+
+/// Sends the state of the battle to a user who reconnected, and resumes it if
+/// nobody else is missing.
+pub fn handle_user_reconnected<U>(room: &mut Room, users: &U, username: &str)
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let battle = match &mut room.battle {
+        RoomBattleStatus::Started(battle) => battle,
+        _ => return,
+    };
+    let party_id = match battle.user_party_id(username) {
+        Some(party_id) => party_id,
+        None => return,
+    };
+    battle.disconnected.retain(|p| *p != party_id);
+
+    let user = &users[username];
+    user.send(battle.snapshot(Some(party_id))).unwrap();
+    if let BattlePhase::ForcedSwitch(slots) = &battle.phase {
+        let state = battle.state.lock().unwrap();
+        for (_, slot) in slots.iter().filter(|(p, _)| *p == party_id) {
+            user.send(BattleRequestSwitch {
+                slot: *slot,
+                legal_indexes: state.party(party_id).switch_targets(),
+            })
+            .unwrap();
+        }
+    }
+    battle.notifier.send(OpponentReconnectedNotify {
+        username: username.to_owned(),
+    });
+    if battle.disconnected.is_empty() {
+        start_turn(room);
+    }
+}
+
+/// Cleans up the battle and the invitations of a room when one of its users
+/// leaves it. Leaving an ongoing battle forfeits it.
+pub fn handle_user_left(room: &mut Room, username: &str, reason: &str) {
+    end_invites_of(room, username, reason);
+    cancel_team_preview(room, username, reason);
+    if let RoomBattleStatus::Started(battle) = &room.battle {
+        if let Some(party_id) = battle.user_party_id(username) {
+            end_battle(room, BattleOutcome::Win(party_id.opposing()), reason);
+        }
+    }
+}
+
+/// Validates an action chosen for the dragon in `slot` of `party_id`, and
+/// plays it once every dragon has one. Returns whether the battle went on.
+pub fn submit_action(
+    battle: &mut Battle,
+    party_id: PartyId,
+    slot: u8,
+    battle_action: BattleAction,
+) -> Result<bool, &'static str> {
+    if slot as usize >= battle.format.active_slots() {
+        return Err("invalid_slot");
+    }
+
+    if let BattlePhase::ForcedSwitch(slots) = &battle.phase {
+        if !slots.contains(&(party_id, slot)) {
+            return Err("waiting_for_switch");
+        }
+        if let BattleAction::UseMove(..) = battle_action {
+            return Err("switch_required");
+        }
+    }
+
+    if let BattleAction::Switch(next_dragon) = battle_action {
+        let state = battle.state.lock().unwrap();
+        if !state.party(party_id).can_switch_to(next_dragon as usize)
+            || battle.switching_to(party_id, next_dragon)
+        {
+            return Err("invalid_switch");
+        }
+    }
+
+    if let BattlePhase::ForcedSwitch(slots) = &mut battle.phase {
+        slots.retain(|s| *s != (party_id, slot));
+        execute_battle_action(party_id, slot, &battle_action, battle);
+        return Ok(true);
+    }
+
+    // The slot stays empty once its dragon fainted with no replacement
+    if battle.slot_fainted(party_id, slot) {
+        return Err("invalid_slot");
+    }
+
+    if battle.locked_action(party_id, slot).is_some() {
+        return Err("action_locked");
+    }
+
+    if battle.has_chosen(party_id, slot) {
+        return Err("action_already_chosen");
+    }
+
+    if let BattleAction::UseMove(move_name, target) = &battle_action {
+        let state = battle.state.lock().unwrap();
+        if !state
+            .party(party_id)
+            .in_slot(slot as usize)
+            .moves
+            .contains(move_name)
+        {
+            return Err("move_not_in_moveset");
+        }
+        if matches!(target, Some(target) if *target as usize >= battle.format.active_slots()) {
+            return Err("invalid_target");
+        }
+    }
+
+    battle.chosen_actions.push((party_id, slot, battle_action));
+    if battle.waiting_parties().is_empty() {
+        run_turn(battle);
+        Ok(true)
     } else {
-        source_user.send_request_error("not_in_battle").unwrap();
+        Ok(false)
+    }
+}
+
+/// Deals with the consequences of the last actions: ends the battle if it is
+/// over, asks for forced switches, or starts the next turn.
+fn advance_battle<U>(room: &mut Room, users: &U)
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let battle = room.battle.unwrap_ref_mut();
+    let new_requests = match settle_battle(battle) {
+        BattleProgress::Over(outcome) => {
+            end_battle(room, outcome, "knockout");
+            return;
+        }
+        BattleProgress::Waiting(new_requests) => new_requests,
+    };
+
+    let state = battle.state.lock().unwrap();
+    for (party_id, slot) in new_requests.iter() {
+        users[battle.party_id_user(*party_id)]
+            .send(BattleRequestSwitch {
+                slot: *slot,
+                legal_indexes: state.party(*party_id).switch_targets(),
+            })
+            .unwrap();
+    }
+    drop(state);
+
+    if !new_requests.is_empty() || matches!(battle.phase, BattlePhase::ChoosingActions) {
+        start_turn(room);
+    }
+}
+
+/// Plays the turns nobody has to choose an action for, and moves the battle
+/// on to its next phase.
+pub fn settle_battle(battle: &mut Battle) -> BattleProgress {
+    loop {
+        let outcome = battle.state.lock().unwrap().outcome();
+        if let Some(outcome) = outcome {
+            return BattleProgress::Over(outcome);
+        }
+
+        let new_requests = update_forced_switches(battle);
+
+        // Nobody can choose an action if every dragon is locked into a move
+        if let BattlePhase::ChoosingActions = battle.phase {
+            if battle.waiting_parties().is_empty() {
+                run_turn(battle);
+                continue;
+            }
+        }
+
+        return BattleProgress::Waiting(new_requests);
+    }
+}
+
+/// Announces that the server is waiting for actions, and starts the timer
+/// that chooses them if nobody does.
+fn start_turn(room: &mut Room) {
+    let battle = room.battle.unwrap_ref_mut();
+    battle.decision += 1;
+    // The turn starts again once everyone is back
+    if !battle.disconnected.is_empty() {
         return;
+    }
+    let settings = battle.timer_settings;
+    let deadline = SystemTime::now() + Duration::from_secs(settings.turn_seconds);
+    battle.notifier.send(BattleTurnStart {
+        turn: battle.turns + 1,
+        deadline: deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+        waiting_for: battle.waiting_usernames(),
+    });
+    room.timer
+        .send(TimerCommand::Start {
+            decision: battle.decision,
+            settings,
+        })
+        .unwrap();
+}
+
+pub fn end_battle(room: &mut Room, outcome: BattleOutcome, reason: &str) {
+    let battle = room.battle.unwrap_ref();
+    battle.notifier.send(battle.end_notify(outcome, reason));
+    room.battle = RoomBattleStatus::None;
+    room.timer.send(TimerCommand::Stop).unwrap();
+    room.send_info();
+}
+
+/// Reminds the users who have not chosen an action yet that time is running
+/// out.
+pub fn warn_turn_timer<R>(mut rooms: R, room_id: &str, decision: u32, seconds_left: u64)
+where
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let room = match rooms.get_mut(room_id) {
+        Some(room) => room,
+        None => return,
     };
-    // let source_party = battle.battlefield.party_mut(source_party_id);
+    let battle = match &room.battle {
+        RoomBattleStatus::Started(battle) if battle.decision == decision => battle,
+        _ => return,
+    };
+    battle.notifier.send(BattleTurnWarning {
+        seconds_left,
+        waiting_for: battle.waiting_usernames(),
+    });
+}
 
-    let battle_action = match req {
-        WsMessage::UseMoveRequest(req) => BattleAction::UseMove(req.move_name),
-        WsMessage::SwitchRequest(req) => BattleAction::Switch(req.next_dragon),
-        _ => unreachable!(),
+/// Chooses a default action for every party that did not choose one in
+/// time, or makes them forfeit if they ran out of time too many times.
+pub fn handle_turn_timeout<U, R>(users: U, mut rooms: R, room_id: &str, decision: u32)
+where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let room = match rooms.get_mut(room_id) {
+        Some(room) => room,
+        None => return,
+    };
+    let battle = match &mut room.battle {
+        RoomBattleStatus::Started(battle) if battle.decision == decision => battle,
+        _ => return,
+    };
+
+    let mut forfeiting = vec![];
+    for party_id in battle.waiting_parties() {
+        let timeouts = battle.timeouts_mut(party_id);
+        *timeouts += 1;
+        let timeouts = *timeouts;
+        battle.notifier.send(BattleTurnTimeout {
+            username: battle.party_id_user(party_id).to_owned(),
+            timeouts,
+            max_timeouts: battle.timer_settings.max_timeouts,
+        });
+        if timeouts >= battle.timer_settings.max_timeouts {
+            forfeiting.push(party_id);
+        } else {
+            for slot in battle.waiting_slots(party_id) {
+                let action = battle.default_action(party_id, slot);
+                submit_action(battle, party_id, slot, action).unwrap();
+            }
+        }
+    }
+
+    match forfeiting[..] {
+        [] => advance_battle(room, &users),
+        [party_id] => end_battle(room, BattleOutcome::Win(party_id.opposing()), "timeout"),
+        _ => end_battle(room, BattleOutcome::Draw, "timeout"),
+    }
+}
+
+/// Holds the battle until the owners of fainted active dragons replace them,
+/// as long as they have dragons left to send in. Returns the slots that were
+/// not requested to be replaced yet.
+fn update_forced_switches(battle: &mut Battle) -> Vec<(PartyId, u8)> {
+    let already_requested = match &battle.phase {
+        BattlePhase::ForcedSwitch(slots) => slots.clone(),
+        BattlePhase::ChoosingActions => vec![],
+    };
+    let state = battle.state.lock().unwrap();
+    let mut fainted = vec![];
+    for party_id in [PartyId::Party1, PartyId::Party2].iter().copied() {
+        let party = state.party(party_id);
+        let replacements = party.switch_targets().len();
+        fainted.extend(
+            (0..party.active.len())
+                .filter(|slot| party.in_slot(*slot).fainted())
+                .take(replacements)
+                .map(|slot| (party_id, slot as u8)),
+        );
+    }
+    drop(state);
+    let new_requests = fainted
+        .iter()
+        .copied()
+        .filter(|slot| !already_requested.contains(slot))
+        .collect();
+    battle.phase = if fainted.is_empty() {
+        BattlePhase::ChoosingActions
+    } else {
+        BattlePhase::ForcedSwitch(fainted)
+    };
+    new_requests
+}
+
+/// Orders the actions of a turn: switches go first, then moves by priority,
+/// then by the speed of the dragon using them, with ties broken randomly.
+fn turn_order(
+    battle: &mut Battle,
+    actions: Vec<(PartyId, u8, BattleAction)>,
+) -> Vec<(PartyId, u8, BattleAction)> {
+    let state = battle.state.lock().unwrap();
+    let order_key = |(party_id, slot, action): &(PartyId, u8, BattleAction)| match action {
+        BattleAction::Switch(_) => (true, 0, 0),
+        BattleAction::UseMove(move_name, _) => (
+            false,
+            move_priority(move_name).unwrap_or(0),
+            state
+                .party(*party_id)
+                .in_slot(*slot as usize)
+                .effective_speed(),
+        ),
     };
+    let mut keyed: Vec<_> = actions
+        .into_iter()
+        .map(|action| (order_key(&action), action))
+        .collect();
+    drop(state);
 
-    if let Some((party_id, action)) = &battle.prepared_action {
-        if execute_battle_action(*party_id, action, &mut battle.battlefield).is_none() {
-            source_user.send_request_error("invalid_move_name").unwrap();
+    // Tied actions are put in a fixed order before shuffling them, so that
+    // the order in which they were chosen does not matter
+    keyed.sort_by(|(a, (a_party, a_slot, _)), (b, (b_party, b_slot, _))| {
+        b.cmp(a)
+            .then(u8::from(*a_party).cmp(&u8::from(*b_party)))
+            .then(a_slot.cmp(b_slot))
+    });
+    let mut start = 0;
+    while start < keyed.len() {
+        let tied = keyed[start..]
+            .iter()
+            .take_while(|(key, _)| *key == keyed[start].0)
+            .count();
+        keyed[start..start + tied].shuffle(&mut battle.rng);
+        start += tied;
+    }
+    keyed.into_iter().map(|(_, action)| action).collect()
+}
+
+/// Plays the chosen actions and the moves the dragons are locked into
+fn run_turn(battle: &mut Battle) {
+    let mut actions = mem::take(&mut battle.chosen_actions);
+    for party_id in [PartyId::Party1, PartyId::Party2].iter().copied() {
+        for slot in 0..battle.format.active_slots() as u8 {
+            if battle.slot_fainted(party_id, slot) {
+                continue;
+            }
+            if let Some(locked) = battle.locked_action(party_id, slot) {
+                actions.push((party_id, slot, locked));
+            }
         }
-        execute_battle_action(party_id.opposing(), &battle_action, &mut battle.battlefield);
-        battle.battlefield.turn();
+    }
+    for (party_id, slot, action) in turn_order(battle, actions).iter() {
+        execute_battle_action(*party_id, *slot, action, battle);
+    }
+    status::end_of_turn(battle);
+    field::end_of_turn(battle);
+    battle.battlefield.turn();
+    battle.turns += 1;
+    battle.draw_offer = None;
+}
+
+/// The opposing slots a move used from `slot` hits: all of them for spread
+/// moves, and the one it is aimed at otherwise. A move aimed at a fainted
+/// dragon hits its partner instead.
+pub fn move_targets(
+    battle: &Battle,
+    party_id: PartyId,
+    slot: u8,
+    target: Option<u8>,
+    spread: bool,
+) -> Vec<usize> {
+    let state = battle.state.lock().unwrap();
+    let opponent = state.party(party_id.opposing());
+    let standing: Vec<usize> = (0..opponent.active.len())
+        .filter(|target| !opponent.in_slot(*target).fainted())
+        .collect();
+    if spread {
+        return standing;
+    }
+    let aimed = (target.unwrap_or(slot) as usize).min(opponent.active.len() - 1);
+    if standing.contains(&aimed) {
+        vec![aimed]
     } else {
-        battle.prepared_action = Some((source_party_id, battle_action));
+        standing.into_iter().take(1).collect()
     }
 }
 
+/// Uses a move on the focused opposing dragon, returning the damage dealt, or
+/// `None` if the move missed it or did not affect it
+fn hit_target(
+    battle: &mut Battle,
+    party_id: PartyId,
+    move_name: &str,
+    move_data: &MoveData,
+    spread: bool,
+) -> Option<u32> {
+    let target = party_id.opposing();
+    if battle.state.lock().unwrap().hidden(target) {
+        battle
+            .notifier
+            .on_miss(party_id, move_name, "semi_invulnerable");
+        return None;
+    }
+
+    let hit_chance = battle.state.lock().unwrap().hit_chance(party_id, move_data);
+    if let Some(hit_chance) = hit_chance {
+        if battle.rng.gen_range(0.0..100.0) >= hit_chance {
+            battle.notifier.on_miss(party_id, move_name, "accuracy");
+            return None;
+        }
+    }
+
+    let target_hp = battle.active_hp(target);
+    // Damage is rolled by the server, as the engine's randomness can not be
+    // seeded, and dealt through the engine
+    if let Some(base_power) = move_data.base_power {
+        let hit = damage::roll_damage(battle, party_id, move_data, base_power, spread);
+        if hit.critical {
+            battle
+                .notifier
+                .on_effect_applied(&battle.battlefield, target, "critical_hit");
+        }
+        let effectiveness = {
+            let mut state = battle.state.lock().unwrap();
+            let effectiveness = state.type_effectiveness(party_id, move_data);
+            state.move_effectiveness = Some(effectiveness);
+            effectiveness
+        };
+        if let Some(attack) = create_move(move_name, hit.damage) {
+            battle.battlefield.attack(party_id, attack.as_ref());
+        }
+        // Immune targets are not affected by the move at all
+        if effectiveness == 0.0 {
+            return None;
+        }
+    }
+    let damage_dealt = target_hp - battle.active_hp(target);
+    apply_move_effects(party_id, move_data, damage_dealt, true, battle);
+    Some(damage_dealt)
+}
+
 fn execute_battle_action(
     party_id: PartyId,
+    slot: u8,
     action: &BattleAction,
-    battlefield: &mut Battlefield<ServerMessenger>,
-) -> Option<WsMessage> {
+    battle: &mut Battle,
+) -> Option<()> {
+    let locked = battle.locked_action(party_id, slot).as_ref() == Some(action);
+    battle
+        .replay
+        .lock()
+        .unwrap()
+        .record_action(battle.turns + 1, party_id, slot, action, locked);
+    battle.focus(party_id, slot as usize);
     match action {
-        BattleAction::UseMove(move_name) => {
-            let attack = create_move(&move_name)?;
-            battlefield.attack(party_id, attack.as_ref());
-            Some(WsMessage::UseMoveNotify(UseMoveNotify {
-                move_name: move_name.clone(),
-                party: party_id.into(),
-            }))
+        BattleAction::UseMove(move_name, target) => {
+            let move_data = move_data(move_name)?;
+            // The dragon might have fainted earlier in the turn
+            if battle.active_fainted(party_id) || status::move_blocked(battle, party_id) {
+                battle
+                    .state
+                    .lock()
+                    .unwrap()
+                    .party_mut(party_id)
+                    .active_mut()
+                    .charging = None;
+                return Some(());
+            }
+
+            if let Some(charge) = &move_data.charge {
+                let mut state = battle.state.lock().unwrap();
+                let dragon = state.party_mut(party_id).active_mut();
+                // The move is used on the turn after charging
+                if dragon.charging.take().is_none() {
+                    dragon.charging = Some(move_name.clone());
+                    drop(state);
+                    battle
+                        .notifier
+                        .on_charge(party_id, move_name, charge.semi_invulnerable);
+                    return Some(());
+                }
+            }
+
+            battle
+                .notifier
+                .on_attack(&battle.battlefield, party_id, move_name);
+            if !move_data.targets_enemy() {
+                apply_move_effects(party_id, move_data, 0, false, battle);
+                return Some(());
+            }
+
+            let targets = move_targets(battle, party_id, slot, *target, move_data.spread);
+            if targets.is_empty() {
+                battle.notifier.on_miss(party_id, move_name, "no_target");
+                return Some(());
+            }
+            let spread = targets.len() > 1;
+            let mut damage_dealt = None;
+            for target in targets {
+                battle.focus(party_id.opposing(), target);
+                if let Some(damage) = hit_target(battle, party_id, move_name, move_data, spread) {
+                    *damage_dealt.get_or_insert(0) += damage;
+                }
+            }
+            // The effects on the user only happen if the move hit anything
+            if let Some(damage_dealt) = damage_dealt {
+                apply_move_effects(party_id, move_data, damage_dealt, false, battle);
+            }
         }
         BattleAction::Switch(new_dragon) => {
-            let next_idx = *new_dragon;
-            let switch_allowed = battlefield.party_mut(party_id).switch(*new_dragon as usize);
-            Some(WsMessage::SwitchNotify(SwitchNotify {
-                party: party_id.into(),
-                next_idx,
-                switch_allowed,
-            }))
+            let original = battle.state.lock().unwrap().party(party_id).active[slot as usize] as u8;
+            // The engine only has one active dragon, the last one switched in,
+            // so it never refuses a switch to a dragon on the bench
+            if !battle
+                .battlefield
+                .party_mut(party_id)
+                .switch(*new_dragon as usize)
+            {
+                return None;
+            }
+            battle.state.lock().unwrap().party_mut(party_id).dragons[original as usize]
+                .switch_out();
+            battle
+                .notifier
+                .on_switch(&battle.battlefield, party_id, original, *new_dragon);
+            field::on_switch_in(battle, party_id);
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{parties, party_slot};
+
+    /// Plays a battle from `seed`, choosing the actions from the turn count
+    /// only, and returns the outcome, the HP of every dragon and the events.
+    /// With `second_first`, the second party chooses its actions first.
+    fn play(
+        format: BattleFormat,
+        seed: u64,
+        second_first: bool,
+    ) -> (Option<Option<u8>>, Vec<u32>, Vec<String>) {
+        let (party1, party2) = parties();
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let mut battle = Battle::new(
+            ("first".to_owned(), "second".to_owned()),
+            (&party1, &party2),
+            format,
+            events_tx,
+            "TEST".to_owned(),
+            seed,
+        )
+        .unwrap();
+
+        let mut outcome = None;
+        for _ in 0..100 {
+            if let BattleProgress::Over(result) = settle_battle(&mut battle) {
+                outcome = Some(match result {
+                    BattleOutcome::Win(party_id) => Some(u8::from(party_id)),
+                    BattleOutcome::Draw => None,
+                });
+                break;
+            }
+            let mut waiting = battle.waiting_parties();
+            if second_first {
+                waiting.reverse();
+            }
+            for party_id in waiting {
+                for slot in battle.waiting_slots(party_id) {
+                    let mut actions = battle.legal_actions(party_id, slot);
+                    let choice = (battle.turns as usize + slot as usize) % actions.len();
+                    submit_action(&mut battle, party_id, slot, actions.remove(choice)).unwrap();
+                }
+            }
+        }
+
+        let state = battle.state.lock().unwrap();
+        let hp = [PartyId::Party1, PartyId::Party2]
+            .iter()
+            .flat_map(|party_id| state.party(*party_id).dragons.iter().map(|d| d.hp))
+            .collect();
+        let mut events = vec![];
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event.to_str().unwrap().to_owned());
+        }
+        (outcome, hp, events)
+    }
+
+    #[test]
+    fn battles_are_reproducible_from_their_seed() {
+        for format in [BattleFormat::Singles, BattleFormat::Doubles].iter() {
+            let first = play(*format, 7, false);
+            assert!(first.0.is_some(), "the battle did not end");
+            assert!(!first.2.is_empty());
+            assert_eq!(first, play(*format, 7, false));
+        }
+    }
+
+    #[test]
+    fn speed_ties_do_not_depend_on_the_order_of_choosing() {
+        // Both parties have a mew, which ties with the other one in doubles
+        for seed in 0..20 {
+            assert_eq!(
+                play(BattleFormat::Doubles, seed, false),
+                play(BattleFormat::Doubles, seed, true)
+            );
+        }
+    }
+
+    #[test]
+    fn validate_party_accepts_valid_parties() {
+        let (party1, party2) = parties();
+        assert_eq!(validate_party(&party1), Ok(()));
+        assert_eq!(validate_party(&party2), Ok(()));
+    }
+
+    #[test]
+    fn validate_party_rejects_invalid_parties() {
+        let valid = party_slot("mew", &["harapas"]);
+        let cases = vec![
+            (vec![], "empty_party"),
+            (
+                vec![valid.clone(); MAX_PARTY_SIZE + 1],
+                "too_many_party_items",
+            ),
+            (
+                vec![party_slot("missingno", &["harapas"])],
+                "invalid_party_item",
+            ),
+            (
+                vec![PartySlot {
+                    level: Some(0),
+                    ..valid.clone()
+                }],
+                "invalid_level",
+            ),
+            (
+                vec![PartySlot {
+                    level: Some(MAX_LEVEL + 1),
+                    ..valid.clone()
+                }],
+                "invalid_level",
+            ),
+            (vec![party_slot("mew", &[])], "no_moves"),
+            (
+                vec![party_slot(
+                    "mew",
+                    &["harapas", "fejeles", "mereg", "fantom", "manover"],
+                )],
+                "too_many_moves",
+            ),
+            (
+                vec![party_slot("mew", &["nincs_ilyen"])],
+                "invalid_move_name",
+            ),
+            (
+                vec![party_slot("mew", &["villamcsapas"])],
+                "move_not_learnable",
+            ),
+            (
+                vec![party_slot("mew", &["harapas", "harapas"])],
+                "duplicate_move",
+            ),
+        ];
+        for (party, reason) in cases {
+            assert_eq!(validate_party(&party), Err(reason));
         }
     }
 }
@@ -0,0 +1,83 @@
+use pokemon_engine::party::PartyId;
+use rand::Rng;
+
+use super::{state::DragonState, Battle};
+use crate::data::moves::MoveData;
+
+/// Chance of a critical hit at each critical hit stage of a move
+const CRIT_CHANCES: [f64; 4] = [1.0 / 24.0, 1.0 / 8.0, 0.5, 1.0];
+const CRIT_MULTIPLIER: f32 = 1.5;
+/// Multiplier of the damage of spread moves hitting more than one dragon
+const SPREAD_MULTIPLIER: f32 = 0.75;
+/// The damage is multiplied by a random percentage between this and 100
+const MIN_DAMAGE_ROLL: u32 = 85;
+/// Scales the damage of moves to the HP of the dragons
+const DAMAGE_DIVISOR: f32 = 3.0;
+
+pub struct Hit {
+    pub damage: u32,
+    pub critical: bool,
+}
+
+/// Rolls the damage a move with `base_power` deals to the focused opposing
+/// dragon, `spread` out over more than one target or not. The randomness
+/// comes from the battle's own generator, so that a battle can be played
+/// again from its seed.
+pub fn roll_damage(
+    battle: &mut Battle,
+    attacker: PartyId,
+    move_data: &MoveData,
+    base_power: u32,
+    spread: bool,
+) -> Hit {
+    let state = battle.state.lock().unwrap();
+    let base_damage = base_damage(
+        state.party(attacker).active(),
+        state.party(attacker.opposing()).active(),
+        base_power,
+    );
+    let multiplier = state.damage_multiplier(attacker, move_data);
+    drop(state);
+
+    if multiplier == 0.0 {
+        return Hit {
+            damage: 0,
+            critical: false,
+        };
+    }
+
+    let crit_stage = (move_data.crit_boost as usize).min(CRIT_CHANCES.len() - 1);
+    let critical = battle.rng.gen_bool(CRIT_CHANCES[crit_stage]);
+    let roll = battle.rng.gen_range(MIN_DAMAGE_ROLL..=100) as f32 / 100.0;
+
+    let crit_multiplier = if critical { CRIT_MULTIPLIER } else { 1.0 };
+    let spread_multiplier = if spread { SPREAD_MULTIPLIER } else { 1.0 };
+    Hit {
+        damage: (base_damage * multiplier * crit_multiplier * spread_multiplier * roll)
+            .round()
+            .max(1.0) as u32,
+        critical,
+    }
+}
+
+/// The damage a move deals on average, ignoring critical hits, for choosing
+/// between moves without rolling
+pub fn expected_damage(
+    attacker: &DragonState,
+    defender: &DragonState,
+    move_data: &MoveData,
+) -> f32 {
+    let base_power = match move_data.base_power {
+        Some(base_power) => base_power,
+        None => return 0.0,
+    };
+    let average_roll = (MIN_DAMAGE_ROLL + 100) as f32 / 200.0;
+    base_damage(attacker, defender, base_power)
+        * attacker.damage_multiplier(defender, move_data)
+        * average_roll
+}
+
+fn base_damage(attacker: &DragonState, defender: &DragonState, base_power: u32) -> f32 {
+    base_power as f32 * attacker.attack as f32 / defender.defense.max(1) as f32 / DAMAGE_DIVISOR
+        + 2.0
+}
@@ -0,0 +1,105 @@
+use pokemon_engine::{battle::Messenger, party::PartyId};
+use rand::Rng;
+
+use super::{
+    field::{add_hazard, clear_hazards, set_weather},
+    stages::change_stat_stage,
+    status::inflict_status,
+    Battle,
+};
+use crate::data::moves::{EffectTarget, MoveData, MoveEffect};
+
+fn target_party(user: PartyId, target: EffectTarget) -> PartyId {
+    match target {
+        EffectTarget::User => user,
+        EffectTarget::Enemy => user.opposing(),
+    }
+}
+
+fn roll(battle: &mut Battle, chance: u8) -> bool {
+    chance >= 100 || battle.rng.gen_range(0..100) < chance
+}
+
+/// Applies the secondary effects of a move used by `party_id`, after its
+/// damage has already been dealt. The effects on the enemy are applied
+/// `on_target`, once to every dragon the move hit, and the others once the
+/// move is done.
+pub fn apply_move_effects(
+    party_id: PartyId,
+    move_data: &MoveData,
+    damage_dealt: u32,
+    on_target: bool,
+    battle: &mut Battle,
+) {
+    for effect in move_data.effects.iter() {
+        let on_enemy = matches!(
+            effect,
+            MoveEffect::StatStages {
+                target: EffectTarget::Enemy,
+                ..
+            } | MoveEffect::Status {
+                target: EffectTarget::Enemy,
+                ..
+            }
+        );
+        if on_enemy != on_target {
+            continue;
+        }
+        match effect {
+            MoveEffect::StatStages {
+                target,
+                stages,
+                chance,
+            } => {
+                let target = target_party(party_id, *target);
+                if battle.active_fainted(target) || !roll(battle, *chance) {
+                    continue;
+                }
+                for (stat, change) in stages.iter() {
+                    change_stat_stage(battle, target, *stat, *change);
+                }
+            }
+            MoveEffect::Status {
+                target,
+                status,
+                chance,
+            } => {
+                let target = target_party(party_id, *target);
+                if battle.active_fainted(target) || !roll(battle, *chance) {
+                    continue;
+                }
+                inflict_status(battle, target, *status);
+            }
+            MoveEffect::Recoil { percent } => {
+                let amount = damage_dealt * *percent as u32 / 100;
+                if amount > 0 && !battle.active_fainted(party_id) {
+                    battle
+                        .notifier
+                        .on_damage(&battle.battlefield, party_id, amount);
+                }
+            }
+            MoveEffect::Heal { percent } => {
+                if battle.active_fainted(party_id) {
+                    continue;
+                }
+                let max_hp = battle.state.lock().unwrap().party(party_id).active().max_hp;
+                battle
+                    .notifier
+                    .on_heal(party_id, max_hp * *percent as u32 / 100);
+            }
+            MoveEffect::SureHit => {
+                let mut state = battle.state.lock().unwrap();
+                state.party_mut(party_id).active_mut().sure_hit = true;
+                drop(state);
+                battle
+                    .notifier
+                    .on_effect_applied(&battle.battlefield, party_id, "sure_hit");
+            }
+            MoveEffect::Weather { weather, turns } => set_weather(battle, *weather, *turns),
+            MoveEffect::Hazard { hazard } => add_hazard(battle, party_id.opposing(), *hazard),
+            MoveEffect::ClearHazards { target } => {
+                clear_hazards(battle, target_party(party_id, *target))
+            }
+        }
+    }
+}
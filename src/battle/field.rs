@@ -0,0 +1,125 @@
+use pokemon_engine::{battle::Messenger, party::PartyId};
+
+use super::Battle;
+use crate::data::moves::{Hazard, Weather};
+
+/// Percentage of the maximum HP lost to an electric storm at the end of
+/// every turn
+const ELECTRIC_STORM_DAMAGE_PERCENT: u32 = 6;
+/// Percentage of the maximum HP lost to scorching stones when switching in
+const SCORCHING_STONES_DAMAGE_PERCENT: u32 = 12;
+
+pub struct ActiveWeather {
+    pub weather: Weather,
+    pub turns_left: u8,
+}
+
+/// Effects on the battlefield that do not belong to any dragon
+#[derive(Default)]
+pub struct FieldState {
+    pub weather: Option<ActiveWeather>,
+    /// Hazards on the side of each party, hurting the dragons switching in
+    pub hazards: (Vec<Hazard>, Vec<Hazard>),
+}
+
+impl FieldState {
+    pub fn hazards(&self, party_id: PartyId) -> &Vec<Hazard> {
+        match party_id {
+            PartyId::Party1 => &self.hazards.0,
+            PartyId::Party2 => &self.hazards.1,
+        }
+    }
+
+    pub fn hazards_mut(&mut self, party_id: PartyId) -> &mut Vec<Hazard> {
+        match party_id {
+            PartyId::Party1 => &mut self.hazards.0,
+            PartyId::Party2 => &mut self.hazards.1,
+        }
+    }
+}
+
+fn damage_percent(battle: &Battle, party_id: PartyId, event: &str, percent: u32) {
+    let max_hp = battle.state.lock().unwrap().party(party_id).active().max_hp;
+    battle
+        .notifier
+        .on_effect_applied(&battle.battlefield, party_id, event);
+    battle.notifier.on_damage(
+        &battle.battlefield,
+        party_id,
+        (max_hp * percent / 100).max(1),
+    );
+}
+
+pub fn set_weather(battle: &mut Battle, weather: Weather, turns: u8) {
+    battle.field.weather = Some(ActiveWeather {
+        weather,
+        turns_left: turns,
+    });
+    battle.notifier.on_weather_change(Some(weather), turns);
+}
+
+pub fn add_hazard(battle: &mut Battle, party_id: PartyId, hazard: Hazard) {
+    let hazards = battle.field.hazards_mut(party_id);
+    if hazards.contains(&hazard) {
+        return;
+    }
+    hazards.push(hazard);
+    battle.notifier.on_hazard_change(party_id, hazard, true);
+}
+
+pub fn clear_hazards(battle: &mut Battle, party_id: PartyId) {
+    let hazards = std::mem::take(battle.field.hazards_mut(party_id));
+    for hazard in hazards {
+        battle.notifier.on_hazard_change(party_id, hazard, false);
+    }
+}
+
+/// Applies the hazards on the side of `party_id` to its newly switched in
+/// dragon.
+pub fn on_switch_in(battle: &mut Battle, party_id: PartyId) {
+    for hazard in battle.field.hazards(party_id).clone() {
+        if battle.active_fainted(party_id) {
+            break;
+        }
+        match hazard {
+            Hazard::ScorchingStones => damage_percent(
+                battle,
+                party_id,
+                "hazard_damage:scorching_stones",
+                SCORCHING_STONES_DAMAGE_PERCENT,
+            ),
+        }
+    }
+}
+
+/// Deals weather damage to the active dragons and makes the weather wear off.
+pub fn end_of_turn(battle: &mut Battle) {
+    let weather = match &mut battle.field.weather {
+        Some(weather) => weather,
+        None => return,
+    };
+    weather.turns_left = weather.turns_left.saturating_sub(1);
+    let (weather, turns_left) = (weather.weather, weather.turns_left);
+
+    for party_id in [PartyId::Party1, PartyId::Party2].iter().copied() {
+        for slot in 0..battle.format.active_slots() {
+            battle.focus(party_id, slot);
+            if battle.active_fainted(party_id) {
+                continue;
+            }
+            match weather {
+                Weather::ElectricStorm => damage_percent(
+                    battle,
+                    party_id,
+                    "weather_damage:electric_storm",
+                    ELECTRIC_STORM_DAMAGE_PERCENT,
+                ),
+            }
+        }
+    }
+
+    if turns_left == 0 {
+        battle.field.weather = None;
+        battle.notifier.on_weather_change(None, 0);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::messages::PartySlot;
+
+/// How many dragons of each party fight at the same time
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BattleFormat {
+    Singles,
+    Doubles,
+}
+
+impl BattleFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "singles" => Some(Self::Singles),
+            "doubles" => Some(Self::Doubles),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Singles => "singles",
+            Self::Doubles => "doubles",
+        }
+    }
+
+    /// The number of active dragons on each side
+    pub fn active_slots(&self) -> usize {
+        match self {
+            Self::Singles => 1,
+            Self::Doubles => 2,
+        }
+    }
+
+    /// Checks that a party has enough dragons to fill its side of the field
+    pub fn check_party(&self, party: &[PartySlot]) -> Result<(), &'static str> {
+        if party.len() < self.active_slots() {
+            return Err("party_too_small");
+        }
+        Ok(())
+    }
+}
+
+/// Parses the format of an invitation, which is singles if it is not given
+pub fn parse_format(name: Option<&str>) -> Result<BattleFormat, &'static str> {
+    name.map_or(Ok(BattleFormat::Singles), |name| {
+        BattleFormat::from_name(name).ok_or("invalid_format")
+    })
+}
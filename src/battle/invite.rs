@@ -0,0 +1,564 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+use log::info;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use super::{
+    begin_battle,
+    format::{parse_format, BattleFormat},
+    preview::{start_team_preview, validate_team_preview},
+    roster,
+    ruleset::{parse_ruleset, Ruleset, TeamPreviewRule},
+    validate_party, RoomBattleStatus,
+};
+use crate::{
+    messages::*,
+    room::{Room, Rooms},
+    user::{User, Users},
+};
+
+/// How long an invitation waits for an answer before it expires
+pub const INVITE_SECONDS: u64 = 60;
+
+/// How the inviting user wants the battle to be played
+#[derive(Clone)]
+pub struct BattleSettings {
+    pub format: BattleFormat,
+    pub ruleset: &'static Ruleset,
+    /// Whether the users choose their dragons after seeing each other's
+    /// parties, and how
+    pub team_preview: Option<TeamPreviewOptions>,
+}
+
+/// A battle invitation waiting for an answer
+pub struct Invite {
+    pub from: String,
+    pub to: String,
+    /// The party of the inviting user
+    pub party: Vec<PartySlot>,
+    pub settings: BattleSettings,
+    pub expires_at: Instant,
+}
+
+/// Checks an invitation before it is sent, returning the settings of the
+/// battle. The invitation only decides about the team preview if the ruleset
+/// does not.
+pub fn validate_invite(
+    party: &[PartySlot],
+    team_preview: Option<TeamPreviewOptions>,
+    format: Option<&str>,
+    ruleset: Option<&str>,
+) -> Result<BattleSettings, &'static str> {
+    let format = parse_format(format)?;
+    let ruleset = parse_ruleset(ruleset)?;
+    let team_preview = match (&ruleset.team_preview, team_preview) {
+        (TeamPreviewRule::Forbidden, Some(_)) => return Err("team_preview_forbidden"),
+        (TeamPreviewRule::Forbidden, None) => None,
+        (TeamPreviewRule::Required(_), Some(_)) => return Err("team_preview_set_by_ruleset"),
+        (TeamPreviewRule::Required(options), None) => Some(options.clone()),
+        (TeamPreviewRule::Optional, team_preview) => team_preview,
+    };
+    validate_party(party)?;
+    ruleset.check_party(party)?;
+    format.check_party(party)?;
+    if let Some(options) = &team_preview {
+        validate_team_preview(options, format)?;
+    }
+    Ok(BattleSettings {
+        format,
+        ruleset,
+        team_preview,
+    })
+}
+
+/// Checks the party of the invited user, which has to be valid for the
+/// format and the ruleset of the invitation
+pub fn check_invited_party(
+    settings: &BattleSettings,
+    party: &[PartySlot],
+) -> Result<(), &'static str> {
+    validate_party(party)?;
+    settings.ruleset.check_party(party)?;
+    settings.format.check_party(party)
+}
+
+fn find_invite(room: &Room, from: &str, to: &str) -> Option<usize> {
+    room.invites
+        .iter()
+        .position(|invite| invite.from == from && invite.to == to)
+}
+
+/// Removes an invitation without starting a battle, telling the room why
+fn end_invite(room: &mut Room, idx: usize, reason: &str) {
+    let invite = room.invites.remove(idx);
+    room.tx
+        .send(
+            BattleInviteEnded {
+                from: invite.from.clone(),
+                to: invite.to.clone(),
+                reason: reason.to_owned(),
+            }
+            .to_message(),
+        )
+        .unwrap();
+}
+
+/// Ends every invitation sent or received by `username`
+pub fn end_invites_of(room: &mut Room, username: &str, reason: &str) {
+    while let Some(idx) = room
+        .invites
+        .iter()
+        .position(|invite| invite.from == username || invite.to == username)
+    {
+        end_invite(room, idx, reason);
+    }
+}
+
+/// Invites `to` to a battle, and returns the index of the invitation
+pub fn send_invite<U>(
+    room: &mut Room,
+    users: &U,
+    room_id: &str,
+    (from, to): (&str, &str),
+    party: Vec<PartySlot>,
+    settings: BattleSettings,
+    rooms_mutex: Rooms,
+) -> usize
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let expires_at = Instant::now() + Duration::from_secs(INVITE_SECONDS);
+    let (format, ruleset) = (settings.format, settings.ruleset);
+    room.invites.push(Invite {
+        from: from.to_owned(),
+        to: to.to_owned(),
+        party,
+        settings,
+        expires_at,
+    });
+    users[to]
+        .send(BattleInvitation {
+            other_user: from.to_owned(),
+            format: format.name().to_owned(),
+            ruleset: ruleset.id.clone(),
+            expires_in: INVITE_SECONDS,
+        })
+        .unwrap();
+    users[from]
+        .send(BattleInviteSent {
+            other_user: to.to_owned(),
+            expires_in: INVITE_SECONDS,
+        })
+        .unwrap();
+    tokio::spawn(expire_invite(
+        room_id.to_owned(),
+        (from.to_owned(), to.to_owned()),
+        expires_at,
+        rooms_mutex,
+    ));
+    room.invites.len() - 1
+}
+
+/// Starts the battle of an invitation, or its team preview, dropping the
+/// other invitations of the room, as it can only have one battle at a time.
+/// The party of the invited user is checked with [`check_invited_party`].
+pub fn accept_invite<U>(
+    room: &mut Room,
+    users: &U,
+    room_id: &str,
+    idx: usize,
+    party: Vec<PartySlot>,
+    mutexes: (Users, Rooms),
+) -> Result<(), &'static str>
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    check_invited_party(&room.invites[idx].settings, &party)?;
+    let invite = room.invites.remove(idx);
+    while !room.invites.is_empty() {
+        end_invite(room, 0, "battle_started");
+    }
+    let usernames = (invite.from, invite.to);
+    if invite.settings.team_preview.is_some() {
+        start_team_preview(
+            room,
+            users,
+            room_id,
+            usernames,
+            (invite.party, party),
+            &invite.settings,
+            mutexes,
+        );
+        Ok(())
+    } else {
+        let rosters = (roster(&invite.party), roster(&party));
+        begin_battle(
+            room,
+            users,
+            usernames,
+            (invite.party, party),
+            rosters,
+            &invite.settings,
+        )
+    }
+}
+
+/// Handles `battle_invite`, `battle_accept`, `battle_decline`,
+/// `battle_cancel_invite`, and `start_battle`, which accepts the invitation
+/// of `other_user` if there is one, and sends one otherwise.
+pub async fn handle_invite_request<U, R>(
+    req: WsMessage,
+    users: U,
+    mut rooms: R,
+    source_username: &str,
+    (users_mutex, rooms_mutex): (Users, Rooms),
+) where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let source_user = &users[source_username];
+    let room_id = match &source_user.current_room_id {
+        Some(id) => id,
+        None => {
+            source_user
+                .send_request_error("no_battle_in_main_room")
+                .unwrap();
+            return;
+        }
+    };
+    let room = rooms.get_mut(room_id).unwrap();
+
+    let req = match req {
+        WsMessage::BattleStartRequest(BattleStartRequest {
+            other_user, party, ..
+        }) if find_invite(room, &other_user, source_username).is_some() => {
+            WsMessage::BattleAcceptRequest(BattleAcceptRequest { other_user, party })
+        }
+        WsMessage::BattleStartRequest(BattleStartRequest {
+            other_user,
+            party,
+            team_preview,
+            format,
+            ruleset,
+        }) => WsMessage::BattleInviteRequest(BattleInviteRequest {
+            other_user,
+            party,
+            team_preview,
+            format,
+            ruleset,
+        }),
+        req => req,
+    };
+
+    let result = match req {
+        WsMessage::BattleInviteRequest(BattleInviteRequest {
+            other_user,
+            party,
+            team_preview,
+            format,
+            ruleset,
+        }) => {
+            if other_user == source_username {
+                Err("cannot_invite_self")
+            } else if !room.users.contains(&other_user) {
+                Err("battle_opponent_not_found")
+            } else if !matches!(room.battle, RoomBattleStatus::None) {
+                Err("ongoing_battle")
+            } else if find_invite(room, source_username, &other_user).is_some() {
+                Err("already_invited")
+            } else if find_invite(room, &other_user, source_username).is_some() {
+                // They should accept it instead
+                Err("already_invited_by_opponent")
+            } else {
+                validate_invite(&party, team_preview, format.as_deref(), ruleset.as_deref()).map(
+                    |settings| {
+                        send_invite(
+                            room,
+                            &users,
+                            room_id,
+                            (source_username, &other_user),
+                            party,
+                            settings,
+                            rooms_mutex,
+                        );
+                    },
+                )
+            }
+        }
+        WsMessage::BattleAcceptRequest(BattleAcceptRequest { other_user, party }) => {
+            if !matches!(room.battle, RoomBattleStatus::None) {
+                Err("ongoing_battle")
+            } else {
+                match find_invite(room, &other_user, source_username) {
+                    None => Err("invite_not_found"),
+                    // The invitation expires at the same moment
+                    Some(idx) if room.invites[idx].expires_at <= Instant::now() => {
+                        end_invite(room, idx, "expired");
+                        Err("invite_expired")
+                    }
+                    Some(idx) => accept_invite(
+                        room,
+                        &users,
+                        room_id,
+                        idx,
+                        party,
+                        (users_mutex, rooms_mutex),
+                    ),
+                }
+            }
+        }
+        WsMessage::BattleDeclineRequest(BattleDeclineRequest { other_user }) => {
+            match find_invite(room, &other_user, source_username) {
+                Some(idx) => {
+                    end_invite(room, idx, "declined");
+                    Ok(())
+                }
+                None => Err("invite_not_found"),
+            }
+        }
+        WsMessage::CancelInviteRequest(CancelInviteRequest { other_user }) => {
+            match find_invite(room, source_username, &other_user) {
+                Some(idx) => {
+                    end_invite(room, idx, "cancelled");
+                    Ok(())
+                }
+                None => Err("invite_not_found"),
+            }
+        }
+        _ => unreachable!(),
+    };
+    if let Err(reason) = result {
+        source_user.send_request_error(reason).unwrap();
+    }
+}
+
+/// Drops an invitation that is still waiting for an answer at `expires_at`
+async fn expire_invite(
+    room_id: String,
+    (from, to): (String, String),
+    expires_at: Instant,
+    rooms: Rooms,
+) {
+    sleep_until(expires_at).await;
+    let mut rooms = rooms.lock().await;
+    let room = match rooms.get_mut(&room_id) {
+        Some(room) => room,
+        None => return,
+    };
+    // The invitation might have been answered, and even sent again since
+    if let Some(idx) = find_invite(room, &from, &to) {
+        if room.invites[idx].expires_at == expires_at {
+            end_invite(room, idx, "expired");
+            info!("Invitation from {} to {} expired", from, to);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::time;
+
+    use super::*;
+    use crate::testing::{parties, party_slot, test_room, TestRoom, ROOM_ID};
+
+    fn start_battle(other_user: &str) -> WsMessage {
+        WsMessage::BattleStartRequest(BattleStartRequest {
+            other_user: other_user.to_owned(),
+            party: parties().0,
+            team_preview: Some(TeamPreviewOptions { bring: None }),
+            format: None,
+            ruleset: None,
+        })
+    }
+
+    fn invite(other_user: &str) -> WsMessage {
+        WsMessage::BattleInviteRequest(BattleInviteRequest {
+            other_user: other_user.to_owned(),
+            party: parties().0,
+            team_preview: Some(TeamPreviewOptions { bring: None }),
+            format: None,
+            ruleset: None,
+        })
+    }
+
+    fn accept(other_user: &str) -> WsMessage {
+        WsMessage::BattleAcceptRequest(BattleAcceptRequest {
+            other_user: other_user.to_owned(),
+            party: parties().1,
+        })
+    }
+
+    async fn request(room: &TestRoom, username: &str, req: WsMessage) {
+        handle_invite_request(
+            req,
+            room.users.lock().await,
+            room.rooms.lock().await,
+            username,
+            (room.users.clone(), room.rooms.clone()),
+        )
+        .await;
+    }
+
+    /// The data of the messages with `action` sent to `username`
+    fn received(room: &mut TestRoom, username: &str, action: &str) -> Vec<Value> {
+        room.received(Some(username))
+            .into_iter()
+            .filter(|message| message["action"] == action)
+            .map(|message| message["data"].clone())
+            .collect()
+    }
+
+    async fn invites(room: &TestRoom) -> Vec<(String, String)> {
+        room.rooms.lock().await[ROOM_ID]
+            .invites
+            .iter()
+            .map(|invite| (invite.from.clone(), invite.to.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn start_battle_invites_or_accepts() {
+        time::pause();
+        let mut room = test_room(&["alice", "bob"], RoomBattleStatus::None);
+        request(&room, "alice", start_battle("bob")).await;
+        assert_eq!(invites(&room).await, [("alice".into(), "bob".into())]);
+        let invitations = received(&mut room, "bob", "battle_invitation");
+        assert_eq!(invitations[0]["other_user"], "alice");
+
+        request(&room, "bob", start_battle("alice")).await;
+        assert!(invites(&room).await.is_empty());
+        assert!(received(&mut room, "bob", "request_error").is_empty());
+        assert!(matches!(
+            room.rooms.lock().await[ROOM_ID].battle,
+            RoomBattleStatus::Preview(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn invitations_of_the_opponent_have_to_be_accepted() {
+        time::pause();
+        let mut room = test_room(&["alice", "bob"], RoomBattleStatus::None);
+        request(&room, "alice", invite("bob")).await;
+        request(&room, "alice", invite("bob")).await;
+        request(&room, "bob", invite("alice")).await;
+
+        let errors = received(&mut room, "alice", "request_error");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["reason"], "already_invited");
+        let errors = received(&mut room, "bob", "request_error");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["reason"], "already_invited_by_opponent");
+        assert_eq!(invites(&room).await, [("alice".into(), "bob".into())]);
+    }
+
+    #[tokio::test]
+    async fn invitations_expire() {
+        time::pause();
+        let mut room = test_room(&["alice", "bob"], RoomBattleStatus::None);
+        request(&room, "alice", invite("bob")).await;
+        time::sleep(Duration::from_secs(INVITE_SECONDS + 1)).await;
+        assert!(invites(&room).await.is_empty());
+        let ended = received(&mut room, "alice", "battle_invite_ended");
+        assert_eq!(ended[0]["reason"], "expired");
+
+        request(&room, "bob", accept("alice")).await;
+        let errors = received(&mut room, "bob", "request_error");
+        assert_eq!(errors[0]["reason"], "invite_not_found");
+    }
+
+    #[tokio::test]
+    async fn invitations_can_not_be_accepted_once_they_expire() {
+        time::pause();
+        let mut room = test_room(&["alice", "bob"], RoomBattleStatus::None);
+        request(&room, "alice", invite("bob")).await;
+        {
+            // The acceptance gets the lock before the expiry does
+            let users = room.users.lock().await;
+            let rooms = room.rooms.lock().await;
+            time::advance(Duration::from_secs(INVITE_SECONDS)).await;
+            handle_invite_request(
+                accept("alice"),
+                users,
+                rooms,
+                "bob",
+                (room.users.clone(), room.rooms.clone()),
+            )
+            .await;
+        }
+        time::sleep(Duration::from_secs(1)).await;
+
+        let errors = received(&mut room, "bob", "request_error");
+        assert_eq!(errors[0]["reason"], "invite_expired");
+        let ended = received(&mut room, "alice", "battle_invite_ended");
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0]["reason"], "expired");
+        assert!(matches!(
+            room.rooms.lock().await[ROOM_ID].battle,
+            RoomBattleStatus::None
+        ));
+    }
+
+    #[tokio::test]
+    async fn accepting_ends_the_other_invitations() {
+        time::pause();
+        let mut room = test_room(&["alice", "bob", "carol"], RoomBattleStatus::None);
+        request(&room, "alice", invite("bob")).await;
+        request(&room, "carol", invite("alice")).await;
+        request(&room, "carol", invite("bob")).await;
+
+        request(&room, "bob", accept("alice")).await;
+        assert!(invites(&room).await.is_empty());
+        time::sleep(Duration::from_secs(1)).await;
+        let ended: Vec<_> = received(&mut room, "carol", "battle_invite_ended")
+            .into_iter()
+            .map(|data| {
+                (
+                    data["from"].clone(),
+                    data["to"].clone(),
+                    data["reason"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ended,
+            [
+                ("carol".into(), "alice".into(), "battle_started".into()),
+                ("carol".into(), "bob".into(), "battle_started".into()),
+            ]
+        );
+
+        request(&room, "carol", invite("alice")).await;
+        let errors = received(&mut room, "carol", "request_error");
+        assert_eq!(errors[0]["reason"], "ongoing_battle");
+    }
+
+    #[test]
+    fn invitations_follow_the_team_preview_of_the_ruleset() {
+        let party = vec![PartySlot {
+            level: Some(50),
+            ..party_slot("mew", &["harapas"])
+        }];
+        let preview = || Some(TeamPreviewOptions { bring: Some(1) });
+        let team_preview = |ruleset, team_preview| {
+            validate_invite(&party, team_preview, None, Some(ruleset))
+                .map(|settings| settings.team_preview.map(|options| options.bring))
+        };
+
+        assert_eq!(team_preview("standard", preview()), Ok(Some(Some(1))));
+        assert_eq!(team_preview("standard", None), Ok(None));
+        assert_eq!(team_preview("tournament", None), Ok(Some(None)));
+        assert_eq!(
+            team_preview("tournament", preview()),
+            Err("team_preview_set_by_ruleset")
+        );
+        assert_eq!(team_preview("blitz", None), Ok(None));
+        assert_eq!(
+            team_preview("blitz", preview()),
+            Err("team_preview_forbidden")
+        );
+    }
+}
@@ -1,29 +1,202 @@
-use pokemon_engine::battle::Messenger;
+use pokemon_engine::{
+    battle::{Battlefield, Messenger},
+    party::PartyId,
+};
 use tokio::sync::mpsc::UnboundedSender;
 use warp::ws::Message;
-use crate::messages::{self, WsSentMessage};
 
+use super::state::SharedBattleState;
+use crate::{
+    data::moves::{Hazard, Stat, Weather},
+    messages::{self, WsSentMessage},
+};
+
+#[derive(Clone)]
 pub struct RoomNotifierMessenger {
-    room_channel: UnboundedSender<Message>
+    /// Forwarded to the room by the replay recorder
+    room_channel: UnboundedSender<Message>,
+    state: SharedBattleState,
+}
+
+impl RoomNotifierMessenger {
+    pub fn new(room_channel: UnboundedSender<Message>, state: SharedBattleState) -> Self {
+        Self {
+            room_channel,
+            state,
+        }
+    }
+
+    /// The slot of the dragon an event of `party` is about
+    fn slot(&self, party: PartyId) -> u8 {
+        self.state.lock().unwrap().party(party).focus as u8
+    }
+
+    /// Sends a battle event that does not come from the battlefield
+    pub fn send<M: WsSentMessage>(&self, message: M) {
+        self.room_channel.send(message.to_message()).unwrap();
+    }
+
+    pub fn on_heal(&self, party: PartyId, amount: u32) {
+        let (slot, amount) = {
+            let mut state = self.state.lock().unwrap();
+            let party_state = state.party_mut(party);
+            let slot = party_state.focus as u8;
+            let dragon = party_state.active_mut();
+            let amount = amount.min(dragon.max_hp - dragon.hp);
+            dragon.hp += amount;
+            (slot, amount)
+        };
+        self.room_channel
+            .send(
+                messages::HealNotify {
+                    party: party.into(),
+                    slot,
+                    amount,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
+    pub fn on_stat_change(&self, party: PartyId, stat: Stat, change: i8, stage: i8) {
+        self.room_channel
+            .send(
+                messages::StatChangeNotify {
+                    party: party.into(),
+                    slot: self.slot(party),
+                    stat: stat.name().into(),
+                    change,
+                    stage,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
+    pub fn on_weather_change(&self, weather: Option<Weather>, turns_left: u8) {
+        self.room_channel
+            .send(
+                messages::WeatherChangeNotify {
+                    weather: weather.map(|w| w.name().into()),
+                    turns_left,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
+    pub fn on_hazard_change(&self, party: PartyId, hazard: Hazard, present: bool) {
+        self.room_channel
+            .send(
+                messages::HazardChangeNotify {
+                    party: party.into(),
+                    hazard: hazard.name().into(),
+                    present,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
+    pub fn on_charge(&self, party: PartyId, move_name: &str, semi_invulnerable: bool) {
+        self.room_channel
+            .send(
+                messages::ChargeNotify {
+                    party: party.into(),
+                    slot: self.slot(party),
+                    move_name: move_name.into(),
+                    semi_invulnerable,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
+    pub fn on_miss(&self, party: PartyId, move_name: &str, reason: &str) {
+        self.room_channel
+            .send(
+                messages::MoveMissedNotify {
+                    party: party.into(),
+                    slot: self.slot(party),
+                    move_name: move_name.into(),
+                    reason: reason.into(),
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
 }
 
 impl Messenger for RoomNotifierMessenger {
-    fn on_attack(&self, field: &pokemon_engine::battle::Battlefield<Self>, party: pokemon_engine::party::PartyId, move_name: &str) {
-        self.room_channel.send(messages::UseMoveNotify {
-            party: party.into(),
-            move_name: move_name.into()
-        }.into_message()).unwrap();
+    fn on_attack(&self, _field: &Battlefield<Self>, party: PartyId, move_name: &str) {
+        self.room_channel
+            .send(
+                messages::UseMoveNotify {
+                    party: party.into(),
+                    slot: self.slot(party),
+                    move_name: move_name.into(),
+                }
+                .to_message(),
+            )
+            .unwrap();
     }
 
-    fn on_damage(&self, field: &pokemon_engine::battle::Battlefield<Self>, party: pokemon_engine::party::PartyId, amount: u32) {
-        
+    fn on_damage(&self, _field: &Battlefield<Self>, party: PartyId, amount: u32) {
+        let (slot, fainted, effectiveness) = {
+            let mut state = self.state.lock().unwrap();
+            let effectiveness = state.move_effectiveness.take();
+            let party_state = state.party_mut(party);
+            let slot = party_state.focus as u8;
+            let dragon = party_state.active_mut();
+            dragon.hp = dragon.hp.saturating_sub(amount);
+            (slot, dragon.fainted(), effectiveness)
+        };
+        self.room_channel
+            .send(
+                messages::DamageNotify {
+                    party: party.into(),
+                    slot,
+                    amount,
+                    fainted,
+                    effectiveness,
+                }
+                .to_message(),
+            )
+            .unwrap();
     }
 
-    fn on_switch(&self, field: &pokemon_engine::battle::Battlefield<Self>, party: pokemon_engine::party::PartyId, original: u8, switched: u8) {
-        
+    fn on_switch(&self, _field: &Battlefield<Self>, party: PartyId, _original: u8, switched: u8) {
+        let mut state = self.state.lock().unwrap();
+        let party_state = state.party_mut(party);
+        let slot = party_state.focus;
+        party_state.active[slot] = switched as usize;
+        party_state.active_mut().revealed = true;
+        let dragon = party_state.active().name.clone();
+        drop(state);
+        self.room_channel
+            .send(
+                messages::SwitchNotify {
+                    party: party.into(),
+                    slot: slot as u8,
+                    next_idx: switched,
+                    dragon,
+                    switch_allowed: true,
+                }
+                .to_message(),
+            )
+            .unwrap();
     }
 
-    fn on_effect_applied(&self, field: &pokemon_engine::battle::Battlefield<Self>, party: pokemon_engine::party::PartyId, effect_desc: &str) {
-        
+    fn on_effect_applied(&self, _field: &Battlefield<Self>, party: PartyId, effect_desc: &str) {
+        self.room_channel
+            .send(
+                messages::EffectNotify {
+                    party: party.into(),
+                    slot: self.slot(party),
+                    effect: effect_desc.into(),
+                }
+                .to_message(),
+            )
+            .unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+use log::info;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use super::{
+    begin_battle, format::BattleFormat, invite::BattleSettings, roster, RoomBattleStatus,
+    MAX_PARTY_SIZE,
+};
+use crate::{
+    messages::*,
+    room::{Room, Rooms},
+    user::{User, Users},
+};
+
+/// How long the users have to choose their dragons
+pub const TEAM_PREVIEW_SECONDS: u64 = 90;
+
+/// Both users see the roster of the other one, and choose which dragons they
+/// bring to the battle, leading with the first one
+pub struct TeamPreview {
+    pub usernames: (String, String),
+    pub settings: BattleSettings,
+    pub parties: (Vec<PartySlot>, Vec<PartySlot>),
+    /// How many dragons each user brings
+    pub bring: (usize, usize),
+    /// The indexes of the dragons each user chose, once they locked in
+    pub choices: (Option<Vec<u8>>, Option<Vec<u8>>),
+    pub expires_at: Instant,
+}
+
+impl TeamPreview {
+    fn side(&self, username: &str) -> Option<Side> {
+        if self.usernames.0 == username {
+            Some(Side::First)
+        } else if self.usernames.1 == username {
+            Some(Side::Second)
+        } else {
+            None
+        }
+    }
+
+    fn party(&self, side: Side) -> &[PartySlot] {
+        match side {
+            Side::First => &self.parties.0,
+            Side::Second => &self.parties.1,
+        }
+    }
+
+    fn bring(&self, side: Side) -> usize {
+        match side {
+            Side::First => self.bring.0,
+            Side::Second => self.bring.1,
+        }
+    }
+
+    fn choice_mut(&mut self, side: Side) -> &mut Option<Vec<u8>> {
+        match side {
+            Side::First => &mut self.choices.0,
+            Side::Second => &mut self.choices.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    First,
+    Second,
+}
+
+/// Checks the team preview settings of an invitation. Users have to bring
+/// enough dragons to fill their side of the field.
+pub fn validate_team_preview(
+    options: &TeamPreviewOptions,
+    format: BattleFormat,
+) -> Result<(), &'static str> {
+    let bring = match options.bring {
+        Some(bring) => bring as usize,
+        None => return Ok(()),
+    };
+    if bring < format.active_slots() || bring > MAX_PARTY_SIZE {
+        return Err("invalid_team_preview");
+    }
+    Ok(())
+}
+
+/// Shows the parties of an accepted invitation to both users, instead of
+/// starting the battle right away
+pub fn start_team_preview<U>(
+    room: &mut Room,
+    users: &U,
+    room_id: &str,
+    usernames: (String, String),
+    parties: (Vec<PartySlot>, Vec<PartySlot>),
+    settings: &BattleSettings,
+    (users_mutex, rooms_mutex): (Users, Rooms),
+) where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let bring_limit = settings
+        .team_preview
+        .as_ref()
+        .and_then(|options| options.bring)
+        .map_or(MAX_PARTY_SIZE, |bring| bring as usize);
+    let expires_at = Instant::now() + Duration::from_secs(TEAM_PREVIEW_SECONDS);
+    let preview = TeamPreview {
+        bring: (
+            parties.0.len().min(bring_limit),
+            parties.1.len().min(bring_limit),
+        ),
+        usernames,
+        settings: settings.clone(),
+        parties,
+        choices: (None, None),
+        expires_at,
+    };
+    users[&preview.usernames.0]
+        .send(TeamPreviewNotify {
+            other_user: preview.usernames.1.clone(),
+            other_party: roster(&preview.parties.1),
+            bring: preview.bring.0 as u8,
+            expires_in: TEAM_PREVIEW_SECONDS,
+        })
+        .unwrap();
+    users[&preview.usernames.1]
+        .send(TeamPreviewNotify {
+            other_user: preview.usernames.0.clone(),
+            other_party: roster(&preview.parties.0),
+            bring: preview.bring.1 as u8,
+            expires_in: TEAM_PREVIEW_SECONDS,
+        })
+        .unwrap();
+    room.battle = RoomBattleStatus::Preview(preview);
+    tokio::spawn(expire_team_preview(
+        room_id.to_owned(),
+        expires_at,
+        users_mutex,
+        rooms_mutex,
+    ));
+}
+
+/// Ends the team preview of a room, and starts the battle with the chosen
+/// dragons. Users who did not lock in bring the first dragons of their party.
+fn finish_team_preview<U>(room: &mut Room, users: &U)
+where
+    U: Deref<Target = HashMap<String, User>>,
+{
+    let preview = match mem::replace(&mut room.battle, RoomBattleStatus::None) {
+        RoomBattleStatus::Preview(preview) => preview,
+        _ => unreachable!(),
+    };
+    let chosen = |party: &[PartySlot], choice: Option<Vec<u8>>, bring: usize| match choice {
+        Some(choice) => choice
+            .into_iter()
+            .map(|idx| party[idx as usize].clone())
+            .collect(),
+        None => party[..bring].to_vec(),
+    };
+    let parties = (
+        chosen(&preview.parties.0, preview.choices.0, preview.bring.0),
+        chosen(&preview.parties.1, preview.choices.1, preview.bring.1),
+    );
+    let rosters = (roster(&preview.parties.0), roster(&preview.parties.1));
+    // The parties were validated with the invitation
+    begin_battle(
+        room,
+        users,
+        preview.usernames,
+        parties,
+        rosters,
+        &preview.settings,
+    )
+    .unwrap();
+}
+
+pub async fn handle_team_preview_request<U, R>(
+    TeamPreviewLockRequest { dragons }: TeamPreviewLockRequest,
+    users: U,
+    mut rooms: R,
+    source_username: &str,
+) where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let source_user = &users[source_username];
+    let room_id = match &source_user.current_room_id {
+        Some(id) => id,
+        None => {
+            source_user
+                .send_request_error("no_battle_in_main_room")
+                .unwrap();
+            return;
+        }
+    };
+    let room = rooms.get_mut(room_id).unwrap();
+    let preview = match &mut room.battle {
+        RoomBattleStatus::Preview(preview) => preview,
+        _ => {
+            source_user.send_request_error("no_team_preview").unwrap();
+            return;
+        }
+    };
+    let side = match preview.side(source_username) {
+        Some(side) => side,
+        None => {
+            source_user.send_request_error("not_in_battle").unwrap();
+            return;
+        }
+    };
+
+    let party_len = preview.party(side).len();
+    let error = if preview.choice_mut(side).is_some() {
+        Some("team_already_locked")
+    } else if dragons.len() != preview.bring(side) {
+        Some("wrong_team_size")
+    } else if dragons.iter().any(|idx| *idx as usize >= party_len) {
+        Some("invalid_party_index")
+    } else if (1..dragons.len()).any(|idx| dragons[..idx].contains(&dragons[idx])) {
+        Some("duplicate_party_index")
+    } else {
+        None
+    };
+    if let Some(reason) = error {
+        source_user.send_request_error(reason).unwrap();
+        return;
+    }
+
+    *preview.choice_mut(side) = Some(dragons);
+    let locked = TeamLockedNotify {
+        username: source_username.to_owned(),
+    }
+    .to_message();
+    users[&preview.usernames.0]
+        .send_raw(locked.clone())
+        .unwrap();
+    users[&preview.usernames.1].send_raw(locked).unwrap();
+
+    if preview.choices.0.is_some() && preview.choices.1.is_some() {
+        finish_team_preview(room, &users);
+    }
+}
+
+/// Cancels the team preview of a room when one of its users leaves
+pub fn cancel_team_preview(room: &mut Room, username: &str, reason: &str) {
+    let preview = match &room.battle {
+        RoomBattleStatus::Preview(preview) if preview.side(username).is_some() => preview,
+        _ => return,
+    };
+    room.tx
+        .send(
+            BattleInviteEnded {
+                from: preview.usernames.0.clone(),
+                to: preview.usernames.1.clone(),
+                reason: reason.to_owned(),
+            }
+            .to_message(),
+        )
+        .unwrap();
+    room.battle = RoomBattleStatus::None;
+}
+
+/// Starts the battle of a team preview that is still going on at
+/// `expires_at`
+async fn expire_team_preview(room_id: String, expires_at: Instant, users: Users, rooms: Rooms) {
+    sleep_until(expires_at).await;
+    let users = users.lock().await;
+    let mut rooms = rooms.lock().await;
+    let room = match rooms.get_mut(&room_id) {
+        Some(room) => room,
+        None => return,
+    };
+    // Another preview might have started since
+    match &room.battle {
+        RoomBattleStatus::Preview(preview) if preview.expires_at == expires_at => {}
+        _ => return,
+    }
+    info!("Team preview in room {} ran out of time", room_id);
+    finish_team_preview(room, &users);
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use super::{timer::TimerSettings, MAX_LEVEL, MAX_PARTY_SIZE};
+use crate::messages::{PartySlot, TeamPreviewOptions};
+
+/// The ruleset of invitations that do not choose one
+pub const DEFAULT_RULESET: &str = "standard";
+
+lazy_static! {
+    static ref RULESETS: HashMap<String, Ruleset> = load_rulesets();
+}
+
+/// Rules both parties of a battle have to follow, chosen with the invitation
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ruleset {
+    /// The key of the ruleset in the data file
+    #[serde(skip_deserializing)]
+    pub id: String,
+    /// User-friendly ruleset name
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The most dragons a party can have
+    #[serde(default = "max_party_size")]
+    pub party_size: usize,
+    /// The highest level a dragon can have
+    #[serde(default = "max_level")]
+    pub level_cap: u8,
+    #[serde(default)]
+    pub banned_dragons: Vec<String>,
+    #[serde(default)]
+    pub banned_moves: Vec<String>,
+    /// Whether a party can only have one dragon of each species
+    #[serde(default)]
+    pub species_clause: bool,
+    #[serde(default)]
+    pub team_preview: TeamPreviewRule,
+    #[serde(default)]
+    pub timer: TimerSettings,
+}
+
+/// Whether the battles of a ruleset begin with a team preview
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamPreviewRule {
+    /// No battle has a team preview
+    Forbidden,
+    /// Every battle has a team preview with these options
+    Required(TeamPreviewOptions),
+    /// The invitation decides about the team preview
+    #[default]
+    Optional,
+}
+
+fn max_party_size() -> usize {
+    MAX_PARTY_SIZE
+}
+
+fn max_level() -> u8 {
+    MAX_LEVEL
+}
+
+impl Ruleset {
+    /// Checks that a party follows the ruleset. The party should have been
+    /// checked with [`validate_party`](super::validate_party).
+    pub fn check_party(&self, party: &[PartySlot]) -> Result<(), &'static str> {
+        if party.len() > self.party_size {
+            return Err("too_many_party_items");
+        }
+        for (idx, slot) in party.iter().enumerate() {
+            if self.banned_dragons.contains(&slot.dragon) {
+                return Err("banned_dragon");
+            }
+            if slot.moves.iter().any(|m| self.banned_moves.contains(m)) {
+                return Err("banned_move");
+            }
+            if slot.level.unwrap_or(MAX_LEVEL) > self.level_cap {
+                return Err("level_too_high");
+            }
+            if self.species_clause && party[..idx].iter().any(|s| s.dragon == slot.dragon) {
+                return Err("species_clause");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load_rulesets() -> HashMap<String, Ruleset> {
+    let mut rulesets: HashMap<String, Ruleset> =
+        serde_json::from_str(include_str!("../data/rulesets.json")).unwrap();
+    for (id, ruleset) in rulesets.iter_mut() {
+        ruleset.id = id.clone();
+    }
+    rulesets
+}
+
+pub fn ruleset(id: &str) -> Option<&'static Ruleset> {
+    RULESETS.get(id)
+}
+
+/// Every ruleset, ordered by their keys
+pub fn rulesets() -> Vec<&'static Ruleset> {
+    let mut rulesets: Vec<&Ruleset> = RULESETS.values().collect();
+    rulesets.sort_by(|a, b| a.id.cmp(&b.id));
+    rulesets
+}
+
+/// Finds the ruleset of an invitation, which is the default one if it is not
+/// given
+pub fn parse_ruleset(id: Option<&str>) -> Result<&'static Ruleset, &'static str> {
+    ruleset(id.unwrap_or(DEFAULT_RULESET)).ok_or("invalid_ruleset")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    fn party_slot(dragon: &str, moves: &[&str], level: Option<u8>) -> PartySlot {
+        PartySlot {
+            level,
+            ..testing::party_slot(dragon, moves)
+        }
+    }
+
+    fn limited() -> Ruleset {
+        serde_json::from_value(json!({
+            "name": "Limited",
+            "party_size": 2,
+            "level_cap": 50,
+            "banned_dragons": ["mewtwo"],
+            "banned_moves": ["mereg"],
+            "species_clause": true
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rulesets_are_loaded_with_their_keys() {
+        let standard = ruleset(DEFAULT_RULESET).unwrap();
+        assert_eq!(standard.id, DEFAULT_RULESET);
+        assert_eq!(standard.party_size, MAX_PARTY_SIZE);
+        assert_eq!(standard.level_cap, MAX_LEVEL);
+        assert!(rulesets().iter().all(|r| ruleset(&r.id).is_some()));
+        assert!(parse_ruleset(None).is_ok());
+        assert_eq!(parse_ruleset(Some("nope")).err(), Some("invalid_ruleset"));
+    }
+
+    #[test]
+    fn check_party_accepts_parties_following_the_rules() {
+        let party = vec![party_slot("mew", &["harapas"], Some(50))];
+        assert_eq!(limited().check_party(&party), Ok(()));
+        let standard = ruleset(DEFAULT_RULESET).unwrap();
+        let party = vec![
+            party_slot("mewtwo", &["harapas"], None),
+            party_slot("mewtwo", &["sortuz"], None),
+        ];
+        assert_eq!(standard.check_party(&party), Ok(()));
+    }
+
+    #[test]
+    fn check_party_rejects_parties_breaking_the_rules() {
+        let mew = party_slot("mew", &["harapas"], Some(50));
+        let cases = vec![
+            (vec![mew.clone(); 3], "too_many_party_items"),
+            (
+                vec![party_slot("mewtwo", &["harapas"], Some(50))],
+                "banned_dragon",
+            ),
+            (
+                vec![party_slot("mew", &["harapas", "mereg"], Some(50))],
+                "banned_move",
+            ),
+            (
+                vec![party_slot("mew", &["harapas"], Some(51))],
+                "level_too_high",
+            ),
+            (
+                vec![party_slot("mew", &["harapas"], None)],
+                "level_too_high",
+            ),
+            (vec![mew.clone(), mew], "species_clause"),
+        ];
+        let ruleset = limited();
+        for (party, reason) in cases {
+            assert_eq!(ruleset.check_party(&party), Err(reason));
+        }
+    }
+}
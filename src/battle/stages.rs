@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use pokemon_engine::party::PartyId;
+
+use super::Battle;
+use crate::data::moves::Stat;
+
+pub const MAX_STAGE: i8 = 6;
+
+/// Stat stages of a dragon, reset when it is switched out
+#[derive(Default, Clone, Copy)]
+pub struct StatStages {
+    pub attack: i8,
+    pub defense: i8,
+    pub speed: i8,
+    pub accuracy: i8,
+    pub evasion: i8,
+}
+
+impl StatStages {
+    pub fn get(&self, stat: Stat) -> i8 {
+        match stat {
+            Stat::Attack => self.attack,
+            Stat::Defense => self.defense,
+            Stat::Speed => self.speed,
+            Stat::Accuracy => self.accuracy,
+            Stat::Evasion => self.evasion,
+        }
+    }
+
+    fn get_mut(&mut self, stat: Stat) -> &mut i8 {
+        match stat {
+            Stat::Attack => &mut self.attack,
+            Stat::Defense => &mut self.defense,
+            Stat::Speed => &mut self.speed,
+            Stat::Accuracy => &mut self.accuracy,
+            Stat::Evasion => &mut self.evasion,
+        }
+    }
+
+    /// Changes a stage, returning by how much it actually changed after
+    /// clamping
+    pub fn change(&mut self, stat: Stat, by: i8) -> i8 {
+        let stage = self.get_mut(stat);
+        let original = *stage;
+        *stage = (original + by).clamp(-MAX_STAGE, MAX_STAGE);
+        *stage - original
+    }
+
+    /// The stages that are not zero, by stat name
+    pub fn changed(&self) -> BTreeMap<String, i8> {
+        [
+            Stat::Attack,
+            Stat::Defense,
+            Stat::Speed,
+            Stat::Accuracy,
+            Stat::Evasion,
+        ]
+        .iter()
+        .filter(|stat| self.get(**stat) != 0)
+        .map(|stat| (stat.name().to_owned(), self.get(*stat)))
+        .collect()
+    }
+
+    pub fn multiplier(&self, stat: Stat) -> f32 {
+        // Accuracy and evasion change in smaller steps
+        let base = match stat {
+            Stat::Accuracy | Stat::Evasion => 3.0,
+            _ => 2.0,
+        };
+        let stage = self.get(stat) as f32;
+        if stage >= 0.0 {
+            (base + stage) / base
+        } else {
+            base / (base - stage)
+        }
+    }
+}
+
+pub fn change_stat_stage(battle: &mut Battle, party_id: PartyId, stat: Stat, by: i8) {
+    let (change, stage) = {
+        let mut state = battle.state.lock().unwrap();
+        let stages = &mut state.party_mut(party_id).active_mut().stages;
+        (stages.change(stat, by), stages.get(stat))
+    };
+    battle
+        .notifier
+        .on_stat_change(party_id, stat, change, stage);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_returns_the_clamped_change() {
+        let mut stages = StatStages::default();
+        assert_eq!(stages.change(Stat::Attack, 2), 2);
+        assert_eq!(stages.change(Stat::Attack, 6), 4);
+        assert_eq!(stages.get(Stat::Attack), MAX_STAGE);
+        assert_eq!(stages.change(Stat::Attack, 1), 0);
+        assert_eq!(stages.change(Stat::Speed, -20), -MAX_STAGE);
+        assert_eq!(stages.get(Stat::Speed), -MAX_STAGE);
+        assert_eq!(stages.get(Stat::Defense), 0);
+    }
+
+    #[test]
+    fn multiplier_depends_on_the_stat() {
+        let mut stages = StatStages::default();
+        assert_eq!(stages.multiplier(Stat::Attack), 1.0);
+        stages.change(Stat::Attack, 2);
+        stages.change(Stat::Defense, -2);
+        stages.change(Stat::Accuracy, 3);
+        stages.change(Stat::Evasion, -3);
+        assert_eq!(stages.multiplier(Stat::Attack), 2.0);
+        assert_eq!(stages.multiplier(Stat::Defense), 0.5);
+        assert_eq!(stages.multiplier(Stat::Accuracy), 2.0);
+        assert_eq!(stages.multiplier(Stat::Evasion), 0.5);
+    }
+}
@@ -0,0 +1,354 @@
+use std::sync::{Arc, Mutex};
+
+use pokemon_engine::party::PartyId;
+
+use super::{stages::StatStages, status::Status, MAX_LEVEL};
+use crate::{
+    data::{
+        dragon_data, move_data,
+        moves::{Hazard, MoveData, Stat},
+        server_dragon_data, type_effectiveness,
+    },
+    messages::{DragonSnapshot, DragonSummary, PartySlot, PartySnapshot, PartySummary},
+};
+
+/// Multiplier of the damage of moves sharing a type with their user
+const SAME_TYPE_BONUS: f32 = 1.5;
+
+/// Scales a base stat to the level of a dragon, keeping it at least 1
+fn scaled_stat(base: u32, level: u8) -> u32 {
+    (base * level as u32 / MAX_LEVEL as u32).max(1)
+}
+
+pub struct DragonState {
+    pub name: String,
+    pub types: Vec<String>,
+    pub hp: u32,
+    pub max_hp: u32,
+    pub attack: u32,
+    pub defense: u32,
+    pub speed: u32,
+    pub moves: Vec<String>,
+    pub status: Option<Status>,
+    pub stages: StatStages,
+    /// Volatile, cleared at the end of every turn
+    pub flinched: bool,
+    /// The two-turn move the dragon is charging, which it will be locked
+    /// into using in the next turn
+    pub charging: Option<String>,
+    /// Whether the moves of the dragon can not miss
+    pub sure_hit: bool,
+    /// Whether the dragon has been on the field, so the opponent can see its
+    /// state
+    pub revealed: bool,
+}
+
+impl DragonState {
+    pub fn new(
+        PartySlot {
+            dragon: name,
+            moves,
+            level,
+        }: &PartySlot,
+    ) -> Option<Self> {
+        let data = dragon_data(name)?;
+        let server_data = server_dragon_data(name)?;
+        let level = level.unwrap_or(MAX_LEVEL);
+        let hp = scaled_stat(data.base_stats.hp, level);
+        Some(Self {
+            name: name.to_owned(),
+            types: server_data.types.clone(),
+            moves: moves.clone(),
+            hp,
+            max_hp: hp,
+            attack: scaled_stat(server_data.base_stats.attack, level),
+            defense: scaled_stat(server_data.base_stats.defense, level),
+            speed: scaled_stat(server_data.base_stats.speed, level),
+            status: None,
+            stages: StatStages::default(),
+            flinched: false,
+            charging: None,
+            sure_hit: false,
+            revealed: false,
+        })
+    }
+
+    /// Resets everything that only lasts while the dragon is on the field
+    pub fn switch_out(&mut self) {
+        self.stages = StatStages::default();
+        self.flinched = false;
+        self.charging = None;
+        self.sure_hit = false;
+    }
+
+    /// Whether the dragon is out of reach of attacks while charging a move
+    pub fn hidden(&self) -> bool {
+        self.charging
+            .as_deref()
+            .and_then(move_data)
+            .and_then(|m| m.charge.as_ref())
+            .is_some_and(|charge| charge.semi_invulnerable)
+    }
+
+    pub fn fainted(&self) -> bool {
+        self.hp == 0
+    }
+
+    /// The multiplier of the damage of a move used by this dragon against
+    /// `defender`: stat stages, the same-type bonus and type effectiveness
+    pub fn damage_multiplier(&self, defender: &DragonState, move_data: &MoveData) -> f32 {
+        let same_type_bonus = if self.types.contains(&move_data.move_type) {
+            SAME_TYPE_BONUS
+        } else {
+            1.0
+        };
+        self.stages.multiplier(Stat::Attack) / defender.stages.multiplier(Stat::Defense)
+            * same_type_bonus
+            * type_effectiveness(&move_data.move_type, &defender.types)
+    }
+
+    /// The speed used for deciding the turn order
+    pub fn effective_speed(&self) -> u32 {
+        let speed = (self.speed as f32 * self.stages.multiplier(Stat::Speed)) as u32;
+        match self.status {
+            Some(Status::Paralysis) => speed / 2,
+            _ => speed,
+        }
+    }
+}
+
+pub struct PartyState {
+    pub dragons: Vec<DragonState>,
+    /// The index of the dragon in each slot of the field. A fainted dragon
+    /// stays in its slot until it is replaced, or for the rest of the battle
+    /// if there is nothing left to replace it with.
+    pub active: Vec<usize>,
+    /// The slot the events of the battlefield are about. The messenger is
+    /// only told the party, so the battle points it at the right dragon
+    /// before anything happens to it.
+    pub focus: usize,
+}
+
+impl PartyState {
+    pub fn new(party: &[PartySlot], slots: usize) -> Option<Self> {
+        let mut dragons: Vec<DragonState> =
+            party.iter().map(DragonState::new).collect::<Option<_>>()?;
+        if dragons.len() < slots {
+            return None;
+        }
+        for dragon in dragons[..slots].iter_mut() {
+            dragon.revealed = true;
+        }
+        Some(Self {
+            dragons,
+            active: (0..slots).collect(),
+            focus: 0,
+        })
+    }
+
+    /// The dragon in the focused slot
+    pub fn active(&self) -> &DragonState {
+        self.in_slot(self.focus)
+    }
+
+    pub fn active_mut(&mut self) -> &mut DragonState {
+        &mut self.dragons[self.active[self.focus]]
+    }
+
+    pub fn in_slot(&self, slot: usize) -> &DragonState {
+        &self.dragons[self.active[slot]]
+    }
+
+    pub fn can_switch_to(&self, idx: usize) -> bool {
+        !self.active.contains(&idx) && self.dragons.get(idx).is_some_and(|d| !d.fainted())
+    }
+
+    pub fn switch_targets(&self) -> Vec<u8> {
+        (0..self.dragons.len())
+            .filter(|idx| self.can_switch_to(*idx))
+            .map(|idx| idx as u8)
+            .collect()
+    }
+
+    pub fn knocked_out(&self) -> bool {
+        self.dragons.iter().all(DragonState::fainted)
+    }
+
+    pub fn summary(&self, username: &str) -> PartySummary {
+        PartySummary {
+            username: username.to_owned(),
+            dragons: self
+                .dragons
+                .iter()
+                .map(|d| DragonSummary {
+                    name: d.name.clone(),
+                    hp: d.hp,
+                    max_hp: d.max_hp,
+                })
+                .collect(),
+        }
+    }
+
+    /// The party as seen by its owner, or by its opponent if `owner` is
+    /// false. The opponent knows the species of the whole party from the
+    /// start of the battle, but only sees the state of the dragons that have
+    /// been on the field.
+    pub fn snapshot(
+        &self,
+        username: &str,
+        owner: bool,
+        hazards: &[Hazard],
+        waiting: bool,
+    ) -> PartySnapshot {
+        PartySnapshot {
+            username: username.to_owned(),
+            active: self.active[0] as u8,
+            active_slots: self.active.iter().map(|idx| *idx as u8).collect(),
+            waiting,
+            hazards: hazards.iter().map(|h| h.name().to_owned()).collect(),
+            dragons: self
+                .dragons
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| owner || d.revealed)
+                .map(|(idx, d)| DragonSnapshot {
+                    index: idx as u8,
+                    name: d.name.clone(),
+                    hp: d.hp,
+                    max_hp: d.max_hp,
+                    status: d.status.map(|s| s.kind().name().to_owned()),
+                    stages: d.stages.changed(),
+                    charging: d.charging.clone(),
+                    moves: if owner { Some(d.moves.clone()) } else { None },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The server's own view of a battle, kept up to date by the messenger, since
+/// the engine does not expose the state of its parties.
+pub struct BattleState {
+    parties: (PartyState, PartyState),
+    /// The type effectiveness of the move being used, reported with the
+    /// damage it deals
+    pub move_effectiveness: Option<f32>,
+}
+
+pub type SharedBattleState = Arc<Mutex<BattleState>>;
+
+impl BattleState {
+    pub fn new(party1: PartyState, party2: PartyState) -> Self {
+        Self {
+            parties: (party1, party2),
+            move_effectiveness: None,
+        }
+    }
+
+    pub fn party(&self, party_id: PartyId) -> &PartyState {
+        match party_id {
+            PartyId::Party1 => &self.parties.0,
+            PartyId::Party2 => &self.parties.1,
+        }
+    }
+
+    pub fn party_mut(&mut self, party_id: PartyId) -> &mut PartyState {
+        match party_id {
+            PartyId::Party1 => &mut self.parties.0,
+            PartyId::Party2 => &mut self.parties.1,
+        }
+    }
+
+    pub fn hidden(&self, party_id: PartyId) -> bool {
+        self.party(party_id).active().hidden()
+    }
+
+    /// The chance of a move used by `attacker` hitting, in percent, or `None`
+    /// if it can not miss
+    pub fn hit_chance(&self, attacker: PartyId, move_data: &MoveData) -> Option<f32> {
+        let attacker_dragon = self.party(attacker).active();
+        if move_data.never_misses || attacker_dragon.sure_hit {
+            return None;
+        }
+        let defender_stages = &self.party(attacker.opposing()).active().stages;
+        Some(
+            move_data.accuracy as f32 * attacker_dragon.stages.multiplier(Stat::Accuracy)
+                / defender_stages.multiplier(Stat::Evasion),
+        )
+    }
+
+    pub fn type_effectiveness(&self, attacker: PartyId, move_data: &MoveData) -> f32 {
+        let defender = self.party(attacker.opposing()).active();
+        type_effectiveness(&move_data.move_type, &defender.types)
+    }
+
+    /// The multiplier of the damage of a move used by `attacker`: stat
+    /// stages, the same-type bonus and type effectiveness
+    pub fn damage_multiplier(&self, attacker: PartyId, move_data: &MoveData) -> f32 {
+        let defender = self.party(attacker.opposing()).active();
+        self.party(attacker)
+            .active()
+            .damage_multiplier(defender, move_data)
+    }
+
+    /// Checks whether either side has run out of dragons that can fight.
+    pub fn outcome(&self) -> Option<BattleOutcome> {
+        match (self.parties.0.knocked_out(), self.parties.1.knocked_out()) {
+            (false, false) => None,
+            (true, true) => Some(BattleOutcome::Draw),
+            (false, true) => Some(BattleOutcome::Win(PartyId::Party1)),
+            (true, false) => Some(BattleOutcome::Win(PartyId::Party2)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum BattleOutcome {
+    Win(PartyId),
+    Draw,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parties;
+
+    fn battle_state() -> BattleState {
+        let (party1, party2) = parties();
+        BattleState::new(
+            PartyState::new(&party1, 1).unwrap(),
+            PartyState::new(&party2, 1).unwrap(),
+        )
+    }
+
+    #[test]
+    fn hit_chance_depends_on_accuracy_and_evasion() {
+        let mut state = battle_state();
+        let storm = move_data("vihar_ereje").unwrap();
+        let bite = move_data("harapas").unwrap();
+        assert_eq!(state.hit_chance(PartyId::Party1, storm), Some(70.0));
+        assert_eq!(state.hit_chance(PartyId::Party1, bite), Some(100.0));
+
+        let defender = state.party_mut(PartyId::Party2).active_mut();
+        defender.stages.change(Stat::Evasion, 3);
+        assert_eq!(state.hit_chance(PartyId::Party1, storm), Some(35.0));
+        let attacker = state.party_mut(PartyId::Party1).active_mut();
+        attacker.stages.change(Stat::Accuracy, 3);
+        assert_eq!(state.hit_chance(PartyId::Party1, storm), Some(70.0));
+    }
+
+    #[test]
+    fn some_moves_never_miss() {
+        let mut state = battle_state();
+        let sure = move_data("sose_hibazik").unwrap();
+        let storm = move_data("vihar_ereje").unwrap();
+        state
+            .party_mut(PartyId::Party2)
+            .active_mut()
+            .stages
+            .change(Stat::Evasion, 6);
+        assert_eq!(state.hit_chance(PartyId::Party1, sure), None);
+        state.party_mut(PartyId::Party1).active_mut().sure_hit = true;
+        assert_eq!(state.hit_chance(PartyId::Party1, storm), None);
+    }
+}
@@ -0,0 +1,137 @@
+use pokemon_engine::{battle::Messenger, party::PartyId};
+use rand::Rng;
+
+use super::Battle;
+use crate::data::moves::StatusKind;
+
+/// Percentage of the maximum HP lost to poison at the end of every turn
+const POISON_DAMAGE_PERCENT: u32 = 14;
+/// Percentage of the maximum HP lost to burn at the end of every turn
+const BURN_DAMAGE_PERCENT: u32 = 16;
+/// Chance of a paralyzed dragon being unable to move, in percent
+const FULL_PARALYSIS_CHANCE: u8 = 25;
+
+/// A status condition that stays on a dragon until it is cured, even when it
+/// is switched out
+#[derive(Clone, Copy)]
+pub enum Status {
+    Poison,
+    Burn,
+    Paralysis,
+    Sleep { turns_left: u8 },
+}
+
+impl Status {
+    pub fn kind(&self) -> StatusKind {
+        match self {
+            Self::Poison => StatusKind::Poison,
+            Self::Burn => StatusKind::Burn,
+            Self::Paralysis => StatusKind::Paralysis,
+            Self::Sleep { .. } => StatusKind::Sleep,
+        }
+    }
+}
+
+/// Status events are reported through the messenger's effect hook, with the
+/// effect described as `<event>:<status>`.
+fn notify(battle: &Battle, party_id: PartyId, event: &str, status: StatusKind) {
+    battle.notifier.on_effect_applied(
+        &battle.battlefield,
+        party_id,
+        &format!("{}:{}", event, status.name()),
+    );
+}
+
+/// Inflicts a status on the active dragon of `party_id`. A dragon can only
+/// have one persistent status at a time.
+pub fn inflict_status(battle: &mut Battle, party_id: PartyId, kind: StatusKind) {
+    let sleep_turns = battle.rng.gen_range(1..=3);
+    let applied = {
+        let mut state = battle.state.lock().unwrap();
+        let dragon = state.party_mut(party_id).active_mut();
+        match kind {
+            StatusKind::Flinch => !std::mem::replace(&mut dragon.flinched, true),
+            _ if dragon.status.is_some() => false,
+            StatusKind::Poison => dragon.status.replace(Status::Poison).is_none(),
+            StatusKind::Burn => dragon.status.replace(Status::Burn).is_none(),
+            StatusKind::Paralysis => dragon.status.replace(Status::Paralysis).is_none(),
+            StatusKind::Sleep => dragon
+                .status
+                .replace(Status::Sleep {
+                    turns_left: sleep_turns,
+                })
+                .is_none(),
+        }
+    };
+    // Flinching is only reported when it actually stops a move
+    if applied && kind != StatusKind::Flinch {
+        notify(battle, party_id, "status_applied", kind);
+    }
+}
+
+/// Checks whether a status prevents the active dragon of `party_id` from
+/// using a move this turn, reporting it if so.
+pub fn move_blocked(battle: &mut Battle, party_id: PartyId) -> bool {
+    let full_paralysis = battle.rng.gen_range(0..100) < FULL_PARALYSIS_CHANCE;
+    let (blocking, cured) = {
+        let mut state = battle.state.lock().unwrap();
+        let dragon = state.party_mut(party_id).active_mut();
+        if dragon.flinched {
+            (Some(StatusKind::Flinch), None)
+        } else {
+            match &mut dragon.status {
+                Some(Status::Sleep { turns_left: 0 }) => {
+                    dragon.status = None;
+                    (None, Some(StatusKind::Sleep))
+                }
+                Some(Status::Sleep { turns_left }) => {
+                    *turns_left -= 1;
+                    (Some(StatusKind::Sleep), None)
+                }
+                Some(Status::Paralysis) if full_paralysis => (Some(StatusKind::Paralysis), None),
+                _ => (None, None),
+            }
+        }
+    };
+    if let Some(kind) = cured {
+        notify(battle, party_id, "status_cured", kind);
+    }
+    if let Some(kind) = blocking {
+        notify(battle, party_id, "status_blocked", kind);
+    }
+    blocking.is_some()
+}
+
+/// Deals residual damage to the active dragons and clears volatile statuses.
+pub fn end_of_turn(battle: &mut Battle) {
+    for party_id in [PartyId::Party1, PartyId::Party2].iter().copied() {
+        for slot in 0..battle.format.active_slots() {
+            let residual = {
+                let mut state = battle.state.lock().unwrap();
+                let party = state.party_mut(party_id);
+                party.focus = slot;
+                let dragon = party.active_mut();
+                dragon.flinched = false;
+                if dragon.fainted() {
+                    continue;
+                }
+                match dragon.status {
+                    Some(Status::Poison) => Some((
+                        StatusKind::Poison,
+                        dragon.max_hp * POISON_DAMAGE_PERCENT / 100,
+                    )),
+                    Some(Status::Burn) => {
+                        Some((StatusKind::Burn, dragon.max_hp * BURN_DAMAGE_PERCENT / 100))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some((kind, amount)) = residual {
+                notify(battle, party_id, "status_tick", kind);
+                battle
+                    .notifier
+                    .on_damage(&battle.battlefield, party_id, amount.max(1));
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::{env, str::FromStr};
+
+use lazy_static::lazy_static;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc::UnboundedReceiver,
+    time::{sleep_until, Duration, Instant},
+};
+
+use crate::{room::Rooms, user::Users};
+
+use super::{handle_turn_timeout, warn_turn_timer};
+
+lazy_static! {
+    static ref DEFAULT_SETTINGS: TimerSettings = TimerSettings::from_env();
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimerSettings {
+    /// How long a party has to choose an action
+    pub turn_seconds: u64,
+    /// How long before the deadline the waiting parties are warned
+    pub warning_seconds: u64,
+    /// How many timeouts a party can have before it forfeits
+    pub max_timeouts: u8,
+    /// How long a disconnected user has to reconnect before forfeiting
+    pub reconnect_seconds: u64,
+}
+
+/// The settings of rulesets that do not set them, which are read from the
+/// environment of the server
+impl Default for TimerSettings {
+    fn default() -> Self {
+        *DEFAULT_SETTINGS
+    }
+}
+
+impl TimerSettings {
+    fn from_env() -> Self {
+        Self {
+            turn_seconds: env_setting("TURN_SECONDS", 60),
+            warning_seconds: env_setting("TURN_WARNING_SECONDS", 10),
+            max_timeouts: env_setting("MAX_TURN_TIMEOUTS", 3),
+            reconnect_seconds: env_setting("RECONNECT_SECONDS", 60),
+        }
+    }
+}
+
+/// Parses the environment variable `name`, or returns `default` if it is not
+/// set or invalid
+fn env_setting<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Invalid value of {}: {}", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+pub enum TimerCommand {
+    /// Starts counting down for a new decision, replacing the previous one
+    Start {
+        decision: u32,
+        settings: TimerSettings,
+    },
+    Stop,
+}
+
+struct RunningTimer {
+    decision: u32,
+    warning: Option<(Instant, u64)>,
+    deadline: Instant,
+}
+
+impl RunningTimer {
+    fn next_wakeup(&self) -> Instant {
+        match self.warning {
+            Some((warning, _)) => warning,
+            None => self.deadline,
+        }
+    }
+}
+
+/// The turn timer of a room. It runs until every sender of `commands` is
+/// dropped, which happens when the room is removed.
+pub async fn run_turn_timer(
+    room_id: String,
+    mut commands: UnboundedReceiver<TimerCommand>,
+    users: Users,
+    rooms: Rooms,
+) {
+    let mut running: Option<RunningTimer> = None;
+    loop {
+        let wakeup = running.as_ref().map(RunningTimer::next_wakeup);
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(TimerCommand::Start { decision, settings }) => {
+                    let now = Instant::now();
+                    let deadline = now + Duration::from_secs(settings.turn_seconds);
+                    let warning = if settings.warning_seconds < settings.turn_seconds {
+                        Some((
+                            deadline - Duration::from_secs(settings.warning_seconds),
+                            settings.warning_seconds,
+                        ))
+                    } else {
+                        None
+                    };
+                    running = Some(RunningTimer { decision, warning, deadline });
+                }
+                Some(TimerCommand::Stop) => running = None,
+                None => return,
+            },
+            _ = sleep_until(wakeup.unwrap_or_else(Instant::now)), if wakeup.is_some() => {
+                let timer = running.as_mut().unwrap();
+                if let Some((_, seconds_left)) = timer.warning.take() {
+                    warn_turn_timer(rooms.lock().await, &room_id, timer.decision, seconds_left);
+                } else {
+                    let decision = timer.decision;
+                    running = None;
+                    let users = users.lock().await;
+                    handle_turn_timeout(users, rooms.lock().await, &room_id, decision);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pokemon_engine::party::PartyId;
+    use tokio::time::{self, Duration};
+
+    use super::*;
+    use crate::{
+        battle::{start_turn, submit_action, RoomBattleStatus},
+        testing::{battle_room, ROOM_ID},
+    };
+
+    fn settings(max_timeouts: u8) -> TimerSettings {
+        TimerSettings {
+            turn_seconds: 60,
+            warning_seconds: 10,
+            max_timeouts,
+            reconnect_seconds: 60,
+        }
+    }
+
+    fn actions(events: &[serde_json::Value]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| event["action"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn users_are_warned_before_the_deadline() {
+        time::pause();
+        let mut room = battle_room(settings(3));
+        start_turn(room.rooms.lock().await.get_mut(ROOM_ID).unwrap());
+
+        time::sleep(Duration::from_secs(45)).await;
+        assert_eq!(actions(&room.received(None)), ["battle_turn_start"]);
+        time::sleep(Duration::from_secs(10)).await;
+        let events = room.received(None);
+        assert_eq!(actions(&events), ["battle_turn_warning"]);
+        assert_eq!(events[0]["data"]["seconds_left"], 10);
+        assert_eq!(
+            events[0]["data"]["waiting_for"],
+            serde_json::json!(["first", "second"])
+        );
+    }
+
+    #[tokio::test]
+    async fn default_actions_are_chosen_at_the_deadline() {
+        time::pause();
+        let mut room = battle_room(settings(3));
+        start_turn(room.rooms.lock().await.get_mut(ROOM_ID).unwrap());
+        room.received(None);
+
+        time::sleep(Duration::from_secs(61)).await;
+        let events = room.received(None);
+        let timeouts: Vec<_> = events
+            .iter()
+            .filter(|event| event["action"] == "battle_turn_timeout")
+            .map(|event| {
+                (
+                    event["data"]["username"].clone(),
+                    event["data"]["timeouts"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            timeouts,
+            [("first".into(), 1.into()), ("second".into(), 1.into())]
+        );
+        assert_eq!(actions(&events).last(), Some(&"battle_turn_start"));
+        let rooms = room.rooms.lock().await;
+        let battle = rooms[ROOM_ID].battle.unwrap_ref();
+        assert_eq!(battle.turns, 1);
+        assert_eq!(battle.timeouts, (1, 1));
+    }
+
+    #[tokio::test]
+    async fn parties_forfeit_after_too_many_timeouts() {
+        time::pause();
+        let mut room = battle_room(settings(2));
+        start_turn(room.rooms.lock().await.get_mut(ROOM_ID).unwrap());
+
+        for _ in 0..2 {
+            {
+                let mut rooms = room.rooms.lock().await;
+                let battle = rooms.get_mut(ROOM_ID).unwrap().battle.unwrap_ref_mut();
+                for slot in battle.waiting_slots(PartyId::Party1) {
+                    let action = battle.default_action(PartyId::Party1, slot);
+                    submit_action(battle, PartyId::Party1, slot, action).unwrap();
+                }
+            }
+            time::sleep(Duration::from_secs(61)).await;
+        }
+
+        let events = room.received(None);
+        let end = events.last().unwrap();
+        assert_eq!(end["action"], "battle_end");
+        assert_eq!(end["data"]["winner"], "first");
+        assert_eq!(end["data"]["reason"], "timeout");
+        let rooms = room.rooms.lock().await;
+        assert!(matches!(rooms[ROOM_ID].battle, RoomBattleStatus::None));
+    }
+
+    #[tokio::test]
+    async fn stopped_timers_do_not_expire() {
+        time::pause();
+        let mut room = battle_room(settings(3));
+        let mut rooms = room.rooms.lock().await;
+        let timed_room = rooms.get_mut(ROOM_ID).unwrap();
+        start_turn(timed_room);
+        timed_room.timer.send(TimerCommand::Stop).unwrap();
+        drop(rooms);
+
+        time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(actions(&room.received(None)), ["battle_turn_start"]);
+    }
+}
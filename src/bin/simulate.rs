@@ -0,0 +1,361 @@
+//! Plays lots of battles between two parties without a server, running the
+//! same battle code, to help balancing the dragons and moves.
+
+use std::{collections::BTreeMap, env, fs, process};
+
+use pokemon_engine::party::PartyId;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use pokemon_ws_server::{
+    battle::{
+        format::BattleFormat,
+        ruleset::{ruleset, DEFAULT_RULESET},
+        settle_battle,
+        state::BattleOutcome,
+        submit_action, validate_party, Battle, BattleAction, BattleProgress,
+    },
+    bot::{LookaheadStrategy, MaxDamageStrategy, RandomStrategy, Strategy},
+    messages::PartySlot,
+};
+
+const USAGE: &str = "\
+usage: simulate <party 1 file> <party 2 file> [options]
+
+Party files contain a party in the format of the start_battle message.
+
+options:
+    --battles <count>        number of battles to play (default: 1000)
+    --seed <seed>            seed of the first battle, the others use the next ones
+    --strategy1 <strategy>   strategy of the first party (default: random)
+    --strategy2 <strategy>   strategy of the second party (default: random)
+    --max-turns <turns>      battles still going after this many turns are left
+                             unfinished (default: 500)
+    --battle-format <format> singles (default) or doubles
+    --ruleset <ruleset>      ruleset the parties have to follow (default: standard)
+    --format <format>        json (default) or csv
+
+strategies:
+    random          chooses any legal action
+    greedy          uses the move with the highest expected damage
+    lookahead       looks one turn ahead like the hard bot, switching out
+                    of bad matchups
+    script:<file>   plays the actions of the file, one per line, in the
+                    `move <move name> [target slot]` or `switch <index>`
+                    format, and chooses randomly once they run out or are
+                    not legal";
+
+enum StrategySpec {
+    Random,
+    Greedy,
+    Lookahead,
+    Script(Vec<BattleAction>),
+}
+
+impl StrategySpec {
+    fn parse(strategy: &str) -> Result<Self, String> {
+        match strategy {
+            "random" => Ok(Self::Random),
+            "greedy" => Ok(Self::Greedy),
+            "lookahead" => Ok(Self::Lookahead),
+            _ => match strategy.strip_prefix("script:") {
+                Some(path) => load_script(path).map(Self::Script),
+                None => Err(format!("unknown strategy: {}", strategy)),
+            },
+        }
+    }
+
+    /// A fresh strategy for a new battle
+    fn build(&self) -> Box<dyn Strategy> {
+        match self {
+            Self::Random => Box::new(RandomStrategy),
+            Self::Greedy => Box::new(MaxDamageStrategy),
+            Self::Lookahead => Box::new(LookaheadStrategy),
+            Self::Script(actions) => Box::new(ScriptStrategy {
+                actions: actions.clone(),
+                step: 0,
+            }),
+        }
+    }
+}
+
+fn load_script(path: &str) -> Result<Vec<BattleAction>, String> {
+    let script = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once(' ') {
+            Some(("move", move_name)) => Ok(parse_move(move_name.trim())),
+            Some(("switch", idx)) => idx
+                .trim()
+                .parse()
+                .map(BattleAction::Switch)
+                .map_err(|_| format!("{}: invalid switch: {}", path, line)),
+            _ => Err(format!("{}: invalid action: {}", path, line)),
+        })
+        .collect()
+}
+
+/// Parses the target slot at the end of a scripted move, if there is one
+fn parse_move(line: &str) -> BattleAction {
+    match line
+        .rsplit_once(' ')
+        .map(|(move_name, target)| (move_name, target.parse()))
+    {
+        Some((move_name, Ok(target))) => {
+            BattleAction::UseMove(move_name.trim().to_owned(), Some(target))
+        }
+        _ => BattleAction::UseMove(line.to_owned(), None),
+    }
+}
+
+/// Plays the actions of a script file in order
+struct ScriptStrategy {
+    actions: Vec<BattleAction>,
+    /// How many actions were chosen in this battle
+    step: usize,
+}
+
+impl Strategy for ScriptStrategy {
+    fn choose(
+        &mut self,
+        battle: &Battle,
+        party_id: PartyId,
+        slot: u8,
+        rng: &mut StdRng,
+    ) -> BattleAction {
+        let legal = battle.legal_actions(party_id, slot);
+        let chosen = self
+            .actions
+            .get(self.step)
+            .filter(|action| legal.contains(action))
+            .cloned();
+        self.step += 1;
+        chosen.unwrap_or_else(|| RandomStrategy.choose(battle, party_id, slot, rng))
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    battles: u32,
+    wins: [u32; 2],
+    draws: u32,
+    unfinished: u32,
+    total_turns: u64,
+    move_usage: [BTreeMap<String, u32>; 2],
+}
+
+fn party_index(party_id: PartyId) -> usize {
+    u8::from(party_id) as usize
+}
+
+fn simulate(
+    parties: &[Vec<PartySlot>; 2],
+    strategies: &[StrategySpec; 2],
+    format: BattleFormat,
+    seed: u64,
+    max_turns: u32,
+    stats: &mut Stats,
+) {
+    // Nobody listens to the events, but the channel has to stay open
+    let (events_tx, _events_rx) = mpsc::unbounded_channel();
+    let mut battle = Battle::new(
+        ("party1".to_owned(), "party2".to_owned()),
+        (&parties[0], &parties[1]),
+        format,
+        events_tx,
+        seed.to_string(),
+        seed,
+    )
+    .unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut strategies = [strategies[0].build(), strategies[1].build()];
+
+    let outcome = loop {
+        match settle_battle(&mut battle) {
+            BattleProgress::Over(outcome) => break Some(outcome),
+            BattleProgress::Waiting(_) if battle.turns >= max_turns => break None,
+            BattleProgress::Waiting(_) => {}
+        }
+        for party_id in battle.waiting_parties() {
+            for slot in battle.waiting_slots(party_id) {
+                let strategy = &mut strategies[party_index(party_id)];
+                let action = strategy.choose(&battle, party_id, slot, &mut rng);
+                submit_action(&mut battle, party_id, slot, action).unwrap();
+            }
+        }
+    };
+
+    stats.battles += 1;
+    stats.total_turns += battle.turns as u64;
+    match outcome {
+        Some(BattleOutcome::Win(party_id)) => stats.wins[party_index(party_id)] += 1,
+        Some(BattleOutcome::Draw) => stats.draws += 1,
+        None => stats.unfinished += 1,
+    }
+    // Locked actions are only the second turn of a move that was already
+    // counted
+    for action in battle.replay.lock().unwrap().actions.iter() {
+        if action.locked {
+            continue;
+        }
+        if let Some(move_name) = action.data["move_name"].as_str() {
+            *stats.move_usage[action.party as usize]
+                .entry(move_name.to_owned())
+                .or_default() += 1;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    battles: u32,
+    draws: u32,
+    unfinished: u32,
+    average_turns: f64,
+    parties: Vec<PartyReport>,
+}
+
+#[derive(Serialize)]
+struct PartyReport {
+    strategy: String,
+    wins: u32,
+    win_rate: f64,
+    /// How many times each move was used in all battles
+    move_usage: BTreeMap<String, u32>,
+}
+
+impl Report {
+    fn new(stats: Stats, strategy_names: [String; 2]) -> Self {
+        let battles = stats.battles.max(1) as f64;
+        let Stats {
+            wins, move_usage, ..
+        } = stats;
+        Self {
+            battles: stats.battles,
+            draws: stats.draws,
+            unfinished: stats.unfinished,
+            average_turns: stats.total_turns as f64 / battles,
+            parties: strategy_names
+                .iter()
+                .zip(wins.iter())
+                .zip(move_usage)
+                .map(|((strategy, wins), move_usage)| PartyReport {
+                    strategy: strategy.clone(),
+                    wins: *wins,
+                    win_rate: *wins as f64 / battles,
+                    move_usage,
+                })
+                .collect(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("party,stat,move,value\n");
+        csv += &format!(",battles,,{}\n", self.battles);
+        csv += &format!(",draws,,{}\n", self.draws);
+        csv += &format!(",unfinished,,{}\n", self.unfinished);
+        csv += &format!(",average_turns,,{}\n", self.average_turns);
+        for (idx, party) in self.parties.iter().enumerate() {
+            csv += &format!("{},wins,,{}\n", idx, party.wins);
+            csv += &format!("{},win_rate,,{}\n", idx, party.win_rate);
+            for (move_name, uses) in party.move_usage.iter() {
+                csv += &format!("{},move_usage,{},{}\n", idx, move_name, uses);
+            }
+        }
+        csv
+    }
+}
+
+fn load_party(path: &str) -> Result<Vec<PartySlot>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let party: Vec<PartySlot> =
+        serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+    validate_party(&party).map_err(|reason| format!("{}: invalid party: {}", path, reason))?;
+    Ok(party)
+}
+
+fn run() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut party_files = vec![];
+    let mut battles: u32 = 1000;
+    let mut seed: u64 = rand::random();
+    let mut strategy_names = ["random".to_owned(), "random".to_owned()];
+    let mut max_turns = 500;
+    let mut format = BattleFormat::Singles;
+    let mut ruleset_id = DEFAULT_RULESET.to_owned();
+    let mut csv = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            party_files.push(arg.as_str());
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        let invalid = || format!("invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--battles" => battles = value.parse().map_err(|_| invalid())?,
+            "--seed" => seed = value.parse().map_err(|_| invalid())?,
+            "--strategy1" => strategy_names[0] = value.clone(),
+            "--strategy2" => strategy_names[1] = value.clone(),
+            "--max-turns" => max_turns = value.parse().map_err(|_| invalid())?,
+            "--battle-format" => format = BattleFormat::from_name(value).ok_or_else(invalid)?,
+            "--ruleset" => ruleset_id = value.clone(),
+            "--format" => {
+                csv = match value.as_str() {
+                    "json" => false,
+                    "csv" => true,
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    let parties = match party_files[..] {
+        [party1, party2] => [load_party(party1)?, load_party(party2)?],
+        _ => return Err(USAGE.to_owned()),
+    };
+    let ruleset = ruleset(&ruleset_id).ok_or_else(|| format!("unknown ruleset: {}", ruleset_id))?;
+    for (party, path) in parties.iter().zip(party_files.iter()) {
+        ruleset
+            .check_party(party)
+            .and_then(|()| format.check_party(party))
+            .map_err(|reason| format!("{}: invalid party: {}", path, reason))?;
+    }
+    let strategies = [
+        StrategySpec::parse(&strategy_names[0])?,
+        StrategySpec::parse(&strategy_names[1])?,
+    ];
+
+    let mut stats = Stats::default();
+    for battle in 0..battles {
+        simulate(
+            &parties,
+            &strategies,
+            format,
+            seed.wrapping_add(battle as u64),
+            max_turns,
+            &mut stats,
+        );
+    }
+
+    let report = Report::new(stats, strategy_names);
+    if csv {
+        print!("{}", report.to_csv());
+    } else {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,723 @@
+//! Built-in opponents that users can battle without a second client. A bot
+//! is a [`User`] whose messages are read by a task instead of a socket, and
+//! which chooses its actions with a [`Strategy`].
+
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+use log::info;
+use pokemon_engine::party::PartyId;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use warp::ws::Message;
+
+use crate::{
+    battle::{
+        damage::expected_damage,
+        handle_in_battle_request,
+        invite::{
+            accept_invite, check_invited_party, end_invites_of, send_invite, validate_invite,
+        },
+        move_targets,
+        preview::handle_team_preview_request,
+        ruleset::Ruleset,
+        state::DragonState,
+        Battle, BattleAction, RoomBattleStatus, MAX_MOVES,
+    },
+    data::{dragon_names, move_data, server_dragon_data},
+    messages::*,
+    room::{Room, Rooms},
+    user::{User, Users},
+};
+
+/// Usernames starting with this are reserved for bots
+pub const BOT_PREFIX: &str = "@bot:";
+
+pub fn is_bot_name(name: &str) -> bool {
+    name.starts_with(BOT_PREFIX)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// Parses the username a battle is requested with, like `@bot:easy`
+    pub fn from_bot_name(name: &str) -> Option<Self> {
+        match name.strip_prefix(BOT_PREFIX)? {
+            "easy" => Some(Self::Easy),
+            "normal" => Some(Self::Normal),
+            "hard" => Some(Self::Hard),
+            _ => None,
+        }
+    }
+
+    pub fn strategy(self) -> Box<dyn Strategy> {
+        match self {
+            Self::Easy => Box::new(RandomStrategy),
+            Self::Normal => Box::new(MaxDamageStrategy),
+            Self::Hard => Box::new(LookaheadStrategy),
+        }
+    }
+}
+
+/// Chooses the actions of a party
+pub trait Strategy: Send {
+    /// Chooses the next action of the dragon in `slot` of `party_id`, which
+    /// has to be one of the legal actions of the battle
+    fn choose(
+        &mut self,
+        battle: &Battle,
+        party_id: PartyId,
+        slot: u8,
+        rng: &mut StdRng,
+    ) -> BattleAction;
+}
+
+fn random_action(battle: &Battle, party_id: PartyId, slot: u8, rng: &mut StdRng) -> BattleAction {
+    battle
+        .legal_actions(party_id, slot)
+        .choose(rng)
+        .unwrap()
+        .clone()
+}
+
+/// The legal actions of a slot, with the opposing slots each of them hits
+fn with_targets(battle: &Battle, party_id: PartyId, slot: u8) -> Vec<(BattleAction, Vec<usize>)> {
+    battle
+        .legal_actions(party_id, slot)
+        .into_iter()
+        .map(|action| {
+            let targets = match &action {
+                BattleAction::UseMove(move_name, target) => {
+                    let spread = move_data(move_name).is_some_and(|m| m.spread);
+                    move_targets(battle, party_id, slot, *target, spread)
+                }
+                BattleAction::Switch(_) => vec![],
+            };
+            (action, targets)
+        })
+        .collect()
+}
+
+/// Chooses any legal action
+pub struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn choose(
+        &mut self,
+        battle: &Battle,
+        party_id: PartyId,
+        slot: u8,
+        rng: &mut StdRng,
+    ) -> BattleAction {
+        random_action(battle, party_id, slot, rng)
+    }
+}
+
+/// Uses the move with the highest expected damage, and chooses randomly when
+/// no move deals damage
+pub struct MaxDamageStrategy;
+
+impl Strategy for MaxDamageStrategy {
+    fn choose(
+        &mut self,
+        battle: &Battle,
+        party_id: PartyId,
+        slot: u8,
+        rng: &mut StdRng,
+    ) -> BattleAction {
+        let legal = with_targets(battle, party_id, slot);
+        let state = battle.state.lock().unwrap();
+        let attacker = state.party(party_id).in_slot(slot as usize);
+        let opponents = state.party(party_id.opposing());
+        let chosen = legal
+            .iter()
+            .filter_map(|(action, targets)| match action {
+                BattleAction::UseMove(move_name, _) => {
+                    let damage = targets
+                        .iter()
+                        .map(|target| move_value(attacker, opponents.in_slot(*target), move_name))
+                        .sum::<f32>();
+                    Some((damage, action))
+                }
+                BattleAction::Switch(_) => None,
+            })
+            .filter(|(damage, _)| *damage > 0.0)
+            .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, action)| action.clone());
+        drop(state);
+        chosen.unwrap_or_else(|| random_action(battle, party_id, slot, rng))
+    }
+}
+
+/// Looks one turn ahead, assuming the dragon across the field answers with
+/// its most damaging move: it prefers knocking that dragon out before it can
+/// move, and switches to a dragon that takes less damage when that is worth
+/// more than attacking.
+pub struct LookaheadStrategy;
+
+impl Strategy for LookaheadStrategy {
+    fn choose(
+        &mut self,
+        battle: &Battle,
+        party_id: PartyId,
+        slot: u8,
+        rng: &mut StdRng,
+    ) -> BattleAction {
+        let legal = with_targets(battle, party_id, slot);
+        let across = move_targets(battle, party_id, slot, None, false);
+        let state = battle.state.lock().unwrap();
+        let own = state.party(party_id);
+        let opponents = state.party(party_id.opposing());
+        // Every opposing dragon might be waiting to be replaced
+        let across = match across.first() {
+            Some(across) => *across,
+            None => {
+                drop(state);
+                return random_action(battle, party_id, slot, rng);
+            }
+        };
+        let opponent = opponents.in_slot(across);
+
+        // The share of the opposing dragons' HP taken, minus the share of
+        // our dragon's HP taken in return
+        let score = |(action, targets): &(BattleAction, Vec<usize>)| {
+            let (dragon, moves_first) = match action {
+                BattleAction::Switch(idx) => (&own.dragons[*idx as usize], true),
+                BattleAction::UseMove(move_name, _) => {
+                    let dragon = own.in_slot(slot as usize);
+                    let priority = move_data(move_name).map_or(0, |m| m.priority);
+                    let moves_first =
+                        (priority, dragon.effective_speed()) > (0, opponent.effective_speed());
+                    (dragon, moves_first)
+                }
+            };
+            let mut dealt = 0.0;
+            let mut knocks_out = false;
+            if let BattleAction::UseMove(move_name, _) = action {
+                for target in targets {
+                    let defender = opponents.in_slot(*target);
+                    let damage = move_value(dragon, defender, move_name).min(defender.hp as f32);
+                    dealt += damage / defender.max_hp.max(1) as f32;
+                    knocks_out |=
+                        *target == across && defender.hp > 0 && damage >= defender.hp as f32;
+                }
+            }
+            let taken = if knocks_out && moves_first {
+                0.0
+            } else {
+                best_move_value(opponent, dragon).min(dragon.hp as f32)
+            };
+            dealt - taken / dragon.max_hp.max(1) as f32
+        };
+        let chosen = legal
+            .iter()
+            .map(|action| (score(action), &action.0))
+            .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+            .map(|(_, action)| action.clone());
+        drop(state);
+        chosen.unwrap_or_else(|| random_action(battle, party_id, slot, rng))
+    }
+}
+
+/// The damage `attacker` is expected to deal with `move_name`, taking its
+/// accuracy into account
+fn move_value(attacker: &DragonState, defender: &DragonState, move_name: &str) -> f32 {
+    move_data(move_name).map_or(0.0, |m| {
+        expected_damage(attacker, defender, m) * m.accuracy as f32 / 100.0
+    })
+}
+
+fn best_move_value(attacker: &DragonState, defender: &DragonState) -> f32 {
+    attacker
+        .moves
+        .iter()
+        .map(|move_name| move_value(attacker, defender, move_name))
+        .fold(0.0, f32::max)
+}
+
+/// The moves of a dragon's learnset that a ruleset allows
+fn allowed_moves(dragon: &str, ruleset: &Ruleset) -> Vec<&'static String> {
+    server_dragon_data(dragon)
+        .unwrap()
+        .moves
+        .iter()
+        .filter(|move_name| move_data(move_name).is_some())
+        .filter(|move_name| !ruleset.banned_moves.contains(move_name))
+        .collect()
+}
+
+/// A party of random dragons with random moves from their learnsets, at the
+/// level cap of the ruleset. Fails if the ruleset does not allow enough
+/// dragons.
+fn random_party(
+    size: usize,
+    ruleset: &Ruleset,
+    rng: &mut StdRng,
+) -> Result<Vec<PartySlot>, &'static str> {
+    let mut names: Vec<&String> = dragon_names()
+        .into_iter()
+        .filter(|dragon| !ruleset.banned_dragons.contains(dragon))
+        .filter(|dragon| !allowed_moves(dragon, ruleset).is_empty())
+        .collect();
+    // The order of a hash map is not stable, but the party should only
+    // depend on the generator
+    names.sort();
+    if names.is_empty() || (ruleset.species_clause && names.len() < size) {
+        return Err("no_bot_party");
+    }
+    let dragons: Vec<&String> = if ruleset.species_clause {
+        names.choose_multiple(rng, size).copied().collect()
+    } else {
+        (0..size).map(|_| *names.choose(rng).unwrap()).collect()
+    };
+    Ok(dragons
+        .into_iter()
+        .map(|dragon| PartySlot {
+            dragon: dragon.clone(),
+            moves: allowed_moves(dragon, ruleset)
+                .choose_multiple(rng, MAX_MOVES)
+                .map(|move_name| (*move_name).clone())
+                .collect(),
+            level: Some(ruleset.level_cap),
+        })
+        .collect())
+}
+
+/// Handles an invitation sent to a bot: the bot joins the room of the user,
+/// and accepts the invitation right away with a party of the same size.
+pub async fn handle_bot_battle_request<U, R>(
+    BattleInviteRequest {
+        other_user: bot,
+        party,
+        team_preview,
+        format,
+        ruleset,
+    }: BattleInviteRequest,
+    mut users: U,
+    mut rooms: R,
+    source_username: &str,
+    users_mutex: Users,
+    rooms_mutex: Rooms,
+) where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let source_user = &users[source_username];
+    let difficulty = match Difficulty::from_bot_name(&bot) {
+        Some(difficulty) => difficulty,
+        None => {
+            source_user.send_request_error("invalid_bot").unwrap();
+            return;
+        }
+    };
+    let room_id = match &source_user.current_room_id {
+        Some(room_id) => room_id.clone(),
+        None => {
+            source_user
+                .send_request_error("no_battle_in_main_room")
+                .unwrap();
+            return;
+        }
+    };
+    let room = rooms.get_mut(&room_id).unwrap();
+    if !matches!(room.battle, RoomBattleStatus::None) {
+        source_user.send_request_error("ongoing_battle").unwrap();
+        return;
+    }
+    let settings =
+        match validate_invite(&party, team_preview, format.as_deref(), ruleset.as_deref()) {
+            Ok(settings) => settings,
+            Err(reason) => {
+                source_user.send_request_error(reason).unwrap();
+                return;
+            }
+        };
+
+    // Bots are named after their room, as every room can have its own
+    let bot_name = format!("{}:{}", bot, room_id);
+    if users.contains_key(&bot_name) {
+        source_user
+            .send_request_error("bot_already_in_room")
+            .unwrap();
+        return;
+    }
+
+    let mut rng = StdRng::from_entropy();
+    let bot_party = match random_party(party.len(), settings.ruleset, &mut rng)
+        .and_then(|bot_party| check_invited_party(&settings, &bot_party).map(|()| bot_party))
+    {
+        Ok(bot_party) => bot_party,
+        Err(reason) => {
+            source_user.send_request_error(reason).unwrap();
+            return;
+        }
+    };
+    // The bot has to be in the room for the invitation, but it only stays if
+    // the battle starts
+    let (tx, rx) = mpsc::unbounded_channel();
+    users.insert(
+        bot_name.clone(),
+        User {
+            name: bot_name.clone(),
+            tx,
+            current_room_id: Some(room_id.clone()),
+            disconnected_at: None,
+        },
+    );
+    room.users.push(bot_name.clone());
+    let idx = send_invite(
+        room,
+        &users,
+        &room_id,
+        (source_username, &bot_name),
+        party,
+        settings,
+        rooms_mutex.clone(),
+    );
+    let accepted = accept_invite(
+        room,
+        &users,
+        &room_id,
+        idx,
+        bot_party,
+        (users_mutex.clone(), rooms_mutex.clone()),
+    );
+    if let Err(reason) = accepted {
+        end_invites_of(room, &bot_name, "cancelled");
+        room.users.retain(|username| *username != bot_name);
+        users.remove(&bot_name);
+        users[source_username].send_request_error(reason).unwrap();
+        return;
+    }
+
+    room.send_info();
+    info!("Bot {} joined room {}", bot_name, room_id);
+    tokio::spawn(run_bot(
+        bot_name,
+        rx,
+        difficulty.strategy(),
+        rng,
+        users_mutex,
+        rooms_mutex,
+    ));
+}
+
+/// Reads the messages sent to a bot, choosing an action whenever the battle
+/// waits for it. The bot leaves the room once the battle is over, or its
+/// invitation ends without a battle, like when the team preview is cancelled.
+async fn run_bot(
+    name: String,
+    mut messages: UnboundedReceiver<Message>,
+    mut strategy: Box<dyn Strategy>,
+    mut rng: StdRng,
+    users: Users,
+    rooms: Rooms,
+) {
+    while let Some(message) = messages.recv().await {
+        let value = message
+            .to_str()
+            .ok()
+            .and_then(|text| serde_json::from_str::<serde_json::Value>(text).ok())
+            .unwrap_or_default();
+        match value["action"].as_str() {
+            Some("battle_turn_start") => {
+                let users = users.lock().await;
+                let rooms = rooms.lock().await;
+                act(&name, strategy.as_mut(), &mut rng, users, rooms).await;
+            }
+            Some("team_preview") => {
+                let users = users.lock().await;
+                let rooms = rooms.lock().await;
+                choose_team(&name, &mut rng, users, rooms).await;
+            }
+            Some("battle_end") => break,
+            Some("battle_invite_ended")
+                if value["data"]["from"] == name.as_str()
+                    || value["data"]["to"] == name.as_str() =>
+            {
+                break
+            }
+            _ => {}
+        }
+    }
+
+    let mut users = users.lock().await;
+    let mut rooms = rooms.lock().await;
+    if let Some(mut bot) = users.remove(&name) {
+        bot.exit_room(&mut rooms);
+        info!("Bot {} left its room", name);
+    }
+}
+
+/// Brings random dragons of the bot to the battle after a team preview
+async fn choose_team<U, R>(name: &str, rng: &mut StdRng, users: U, rooms: R)
+where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let preview = match users[name]
+        .current_room_id
+        .as_ref()
+        .and_then(|room_id| rooms.get(room_id))
+        .map(|room| &room.battle)
+    {
+        Some(RoomBattleStatus::Preview(preview)) => preview,
+        _ => return,
+    };
+    // The bot is always the invited user
+    let party_len = preview.parties.1.len() as u8;
+    let dragons = (0..party_len)
+        .collect::<Vec<_>>()
+        .choose_multiple(rng, preview.bring.1)
+        .copied()
+        .collect();
+    handle_team_preview_request(TeamPreviewLockRequest { dragons }, users, rooms, name).await;
+}
+
+/// Submits the actions of the bot for every slot the battle is waiting for,
+/// the same way a client would
+async fn act<U, R>(
+    name: &str,
+    strategy: &mut dyn Strategy,
+    rng: &mut StdRng,
+    mut users: U,
+    mut rooms: R,
+) where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+    R: DerefMut + Deref<Target = HashMap<String, Room>>,
+{
+    let mut slots: Option<Vec<u8>> = None;
+    loop {
+        let battle = match users[name]
+            .current_room_id
+            .as_ref()
+            .and_then(|room_id| rooms.get(room_id))
+            .map(|room| &room.battle)
+        {
+            Some(RoomBattleStatus::Started(battle)) => battle,
+            _ => return,
+        };
+        let party_id = match battle.user_party_id(name) {
+            Some(party_id) => party_id,
+            None => return,
+        };
+        // Only the slots that were waiting at first, as the next turn starts
+        // once they all have an action
+        let waiting = battle.waiting_slots(party_id);
+        let remaining = slots.get_or_insert_with(|| waiting.clone());
+        remaining.retain(|slot| waiting.contains(slot));
+        if remaining.is_empty() {
+            return;
+        }
+        let slot = remaining.remove(0);
+        let request = match strategy.choose(battle, party_id, slot, rng) {
+            BattleAction::UseMove(move_name, target) => WsMessage::UseMoveRequest(UseMoveRequest {
+                move_name,
+                slot: Some(slot),
+                target,
+            }),
+            BattleAction::Switch(next_dragon) => WsMessage::SwitchRequest(SwitchRequest {
+                next_dragon,
+                slot: Some(slot),
+            }),
+        };
+        handle_in_battle_request(request, &mut *users, &mut *rooms, name).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{self, Duration};
+
+    use super::*;
+    use crate::{
+        battle::{
+            format::BattleFormat, preview::cancel_team_preview, settle_battle, submit_action,
+            BattleProgress,
+        },
+        testing::{parties, test_room, TestRoom, ROOM_ID},
+    };
+
+    const BOT_NAME: &str = "@bot:easy:TEST";
+
+    #[test]
+    fn strategies_choose_legal_actions() {
+        let (party1, party2) = parties();
+        let difficulties = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+        for format in [BattleFormat::Singles, BattleFormat::Doubles].iter() {
+            for seed in 0..difficulties.len() as u64 {
+                let (events_tx, _events) = mpsc::unbounded_channel();
+                let mut battle = Battle::new(
+                    ("first".to_owned(), "second".to_owned()),
+                    (&party1, &party2),
+                    *format,
+                    events_tx,
+                    ROOM_ID.to_owned(),
+                    seed,
+                )
+                .unwrap();
+                // Every strategy plays against the next one
+                let mut strategies = [
+                    difficulties[seed as usize].strategy(),
+                    difficulties[(seed as usize + 1) % difficulties.len()].strategy(),
+                ];
+                let mut rng = StdRng::seed_from_u64(seed);
+                for _ in 0..100 {
+                    if let BattleProgress::Over(_) = settle_battle(&mut battle) {
+                        break;
+                    }
+                    for party_id in battle.waiting_parties() {
+                        let strategy = &mut strategies[u8::from(party_id) as usize];
+                        for slot in battle.waiting_slots(party_id) {
+                            let action = strategy.choose(&battle, party_id, slot, &mut rng);
+                            assert!(battle.legal_actions(party_id, slot).contains(&action));
+                            submit_action(&mut battle, party_id, slot, action).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn invite(team_preview: Option<TeamPreviewOptions>) -> BattleInviteRequest {
+        BattleInviteRequest {
+            other_user: "@bot:easy".to_owned(),
+            party: parties().0,
+            team_preview,
+            format: None,
+            ruleset: None,
+        }
+    }
+
+    fn preview() -> Option<TeamPreviewOptions> {
+        Some(TeamPreviewOptions { bring: None })
+    }
+
+    fn request_errors(room: &mut TestRoom) -> Vec<String> {
+        room.received(Some("player"))
+            .into_iter()
+            .filter(|message| message["action"] == "request_error")
+            .map(|message| message["data"]["reason"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn bots_join_the_room_of_their_invitation() {
+        time::pause();
+        let mut room = test_room(&["player"], RoomBattleStatus::None);
+        let (users, rooms) = (room.users.clone(), room.rooms.clone());
+        handle_bot_battle_request(
+            invite(preview()),
+            users.lock().await,
+            rooms.lock().await,
+            "player",
+            users.clone(),
+            rooms.clone(),
+        )
+        .await;
+
+        assert!(request_errors(&mut room).is_empty());
+        assert!(users.lock().await[BOT_NAME].current_room_id.is_some());
+        let rooms = rooms.lock().await;
+        assert_eq!(rooms[ROOM_ID].users, ["player", BOT_NAME]);
+        assert!(matches!(
+            rooms[ROOM_ID].battle,
+            RoomBattleStatus::Preview(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn bots_leave_when_their_team_preview_is_cancelled() {
+        time::pause();
+        let mut room = test_room(&["player"], RoomBattleStatus::None);
+        let (users, rooms) = (room.users.clone(), room.rooms.clone());
+        handle_bot_battle_request(
+            invite(preview()),
+            users.lock().await,
+            rooms.lock().await,
+            "player",
+            users.clone(),
+            rooms.clone(),
+        )
+        .await;
+
+        {
+            let mut users = users.lock().await;
+            let mut rooms = rooms.lock().await;
+            cancel_team_preview(rooms.get_mut(ROOM_ID).unwrap(), "player", "left_room");
+            // The bot is still there until it reads that its invitation ended
+            handle_bot_battle_request(
+                invite(preview()),
+                &mut *users,
+                &mut *rooms,
+                "player",
+                room.users.clone(),
+                room.rooms.clone(),
+            )
+            .await;
+        }
+        assert_eq!(request_errors(&mut room), ["bot_already_in_room"]);
+
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(!users.lock().await.contains_key(BOT_NAME));
+        assert_eq!(rooms.lock().await[ROOM_ID].users, ["player"]);
+
+        handle_bot_battle_request(
+            invite(preview()),
+            users.lock().await,
+            rooms.lock().await,
+            "player",
+            users.clone(),
+            rooms.clone(),
+        )
+        .await;
+        assert!(request_errors(&mut room).is_empty());
+        assert!(users.lock().await.contains_key(BOT_NAME));
+    }
+
+    #[tokio::test]
+    async fn bots_are_not_added_for_invalid_invitations() {
+        time::pause();
+        let mut room = test_room(&["player"], RoomBattleStatus::None);
+        let (users, rooms) = (room.users.clone(), room.rooms.clone());
+        let requests = vec![
+            BattleInviteRequest {
+                other_user: "@bot:unbeatable".to_owned(),
+                ..invite(None)
+            },
+            BattleInviteRequest {
+                party: vec![],
+                ..invite(None)
+            },
+            BattleInviteRequest {
+                ruleset: Some("nope".to_owned()),
+                ..invite(None)
+            },
+        ];
+        for request in requests {
+            handle_bot_battle_request(
+                request,
+                users.lock().await,
+                rooms.lock().await,
+                "player",
+                users.clone(),
+                rooms.clone(),
+            )
+            .await;
+        }
+
+        assert_eq!(
+            request_errors(&mut room),
+            ["invalid_bot", "empty_party", "invalid_ruleset"]
+        );
+        assert!(!users.lock().await.contains_key(BOT_NAME));
+        assert_eq!(rooms.lock().await[ROOM_ID].users, ["player"]);
+    }
+}
@@ -1,13 +1,27 @@
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
 use rand::Rng;
-use tokio::sync::mpsc::{self, error::SendError, UnboundedSender};
+use tokio::{
+    sync::mpsc::{self, error::SendError, UnboundedSender},
+    time::{sleep_until, Duration, Instant},
+};
 use warp::ws::{Message, WebSocket};
 
 use crate::{
-    battle::{handle_battle_request, handle_in_battle_request},
+    battle::{
+        handle_battle_end_request, handle_battle_state_request, handle_in_battle_request,
+        handle_user_disconnected, handle_user_reconnected, invite::handle_invite_request,
+        preview::handle_team_preview_request, ruleset::rulesets, timer::run_turn_timer,
+        RoomBattleStatus,
+    },
+    bot::{handle_bot_battle_request, is_bot_name},
     messages::*,
-    room::{Room, Rooms},
+    room::{run_room_broadcast, Room, Rooms},
     user::{SingleUser, User, Users},
 };
 
@@ -18,28 +32,54 @@ pub async fn ws_handler(ws: WebSocket, name: String, users: Users, rooms: Rooms)
         name,
         tx: tx.clone(),
         current_room_id: None,
+        disconnected_at: None,
     };
+    let reconnected;
     {
         let mut users = users.lock().await;
-        if users.contains_key(&user.name) {
-            sock_tx
-                .send(UserExistsMessage {}.into_message())
-                .await
-                .unwrap();
-            sock_tx.close().await.unwrap();
-            return;
+        match users.get_mut(&user.name) {
+            // The user lost their connection during a battle, and takes their
+            // place back
+            Some(existing) if existing.disconnected_at.is_some() => {
+                reconnected = true;
+                reconnect_user(&mut *users, &rooms, &user.name, tx.clone()).await;
+            }
+            // Bot names are reserved
+            _ if is_bot_name(&user.name) => {
+                sock_tx
+                    .send(UserExistsMessage {}.to_message())
+                    .await
+                    .unwrap();
+                sock_tx.close().await.unwrap();
+                return;
+            }
+            Some(_) => {
+                sock_tx
+                    .send(UserExistsMessage {}.to_message())
+                    .await
+                    .unwrap();
+                sock_tx.close().await.unwrap();
+                return;
+            }
+            None => {
+                reconnected = false;
+                users.insert(user.name.clone(), user.clone());
+            }
         }
-        users.insert(user.name.clone(), user.clone());
     }
 
-    info!("Client connected with name: {}", user.name);
+    if reconnected {
+        info!("Client reconnected with name: {}", user.name);
+    } else {
+        info!("Client connected with name: {}", user.name);
+    }
 
     let welcome = WelcomeMessage { name: &user.name };
-    {
+    if !reconnected {
         let users = users.lock().await;
         broadcast(
             users.values().filter(|u| u.current_room_id.is_none()),
-            welcome.into_message(),
+            welcome.to_message(),
         )
         .await
         .unwrap();
@@ -81,10 +121,98 @@ pub async fn ws_handler(ws: WebSocket, name: String, users: Users, rooms: Rooms)
             .await
             .unwrap();
     }
+    handle_connection_lost(&user.name, users, rooms).await;
+}
+
+/// Gives a user who lost their connection during a battle their place back,
+/// sending their messages to `tx` from now on
+async fn reconnect_user<U>(
+    mut users: U,
+    rooms: &Rooms,
+    username: &str,
+    tx: UnboundedSender<Message>,
+) where
+    U: DerefMut + Deref<Target = HashMap<String, User>>,
+{
+    let user = users.get_mut(username).unwrap();
+    user.tx = tx;
+    user.disconnected_at = None;
+    if let Some(room_id) = user.current_room_id.clone() {
+        user.send(RoomJoinReply {
+            room_id: room_id.clone(),
+            succeeded: true,
+        })
+        .unwrap();
+        let mut rooms = rooms.lock().await;
+        if let Some(room) = rooms.get_mut(&room_id) {
+            handle_user_reconnected(room, &users, username);
+        }
+    }
+}
+
+/// Removes a user whose connection was closed, unless they are in a battle,
+/// where they have some time to reconnect.
+async fn handle_connection_lost(username: &str, users_mutex: Users, rooms_mutex: Rooms) {
+    let mut users = users_mutex.lock().await;
+    let mut rooms = rooms_mutex.lock().await;
+    let current_room_id = users[username].current_room_id.clone();
+    let reconnect_seconds = match &current_room_id {
+        Some(room_id) => handle_user_disconnected(rooms.get_mut(room_id).unwrap(), username),
+        None => None,
+    };
+    match reconnect_seconds {
+        Some(seconds) => {
+            info!(
+                "User {} disconnected during a battle, waiting {} seconds for them",
+                username, seconds
+            );
+            let disconnected_at = Instant::now();
+            let user = users.get_mut(username).unwrap();
+            user.disconnected_at = Some(disconnected_at);
+            user.tx = discarding_sender();
+            tokio::spawn(expire_reconnection(
+                username.to_owned(),
+                disconnected_at,
+                Duration::from_secs(seconds),
+                users_mutex.clone(),
+                rooms_mutex.clone(),
+            ));
+        }
+        None => {
+            let mut user = users.remove(username).unwrap();
+            info!("User {} disconnected", user.name);
+            user.exit_room(&mut rooms);
+        }
+    }
+}
+
+/// Removes a disconnected user whose reconnection window is over, making them
+/// lose their battle.
+async fn expire_reconnection(
+    username: String,
+    disconnected_at: Instant,
+    window: Duration,
+    users: Users,
+    rooms: Rooms,
+) {
+    sleep_until(disconnected_at + window).await;
     let mut users = users.lock().await;
-    let mut user = users.remove(&user.name).unwrap();
-    info!("User {} disconnected", user.name);
-    user.exit_room(&mut rooms.lock().await);
+    match users.get(&username) {
+        // The user might have reconnected, and even disconnected again since
+        Some(user) if user.disconnected_at == Some(disconnected_at) => {}
+        _ => return,
+    }
+    let mut user = users.remove(&username).unwrap();
+    info!("User {} did not reconnect in time", username);
+    user.exit_room_with_reason(&mut rooms.lock().await, "disconnected");
+}
+
+/// A channel for a user without a socket, which discards everything sent to
+/// it.
+fn discarding_sender() -> UnboundedSender<Message> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+    tx
 }
 
 async fn handle_message(
@@ -107,11 +235,11 @@ async fn handle_message(
                     let rooms = rooms.lock().await;
                     let room = &rooms[id];
                     for username in room.users.iter() {
-                        users[username].tx.send(chat.into_message()).unwrap();
+                        users[username].tx.send(chat.to_message()).unwrap();
                     }
                 }
                 None => {
-                    if let Err(e) = broadcast(users.values(), chat.into_message()).await {
+                    if let Err(e) = broadcast(users.values(), chat.to_message()).await {
                         error!("While broadcasting a chat message: {}", e);
                         return Err(());
                     }
@@ -126,28 +254,33 @@ async fn handle_message(
             // let mut users = users.lock().await;
             // let user = users.get_mut(&user.name).unwrap();
 
-            let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+            let (tx, rx) = mpsc::unbounded_channel::<Message>();
+            let (timer_tx, timer_rx) = mpsc::unbounded_channel();
             let mut rooms_lock = rooms.lock().await;
-            let room = Room::new(user.name.clone(), tx);
+            let room = Room::new(user.name.clone(), tx, timer_tx);
             user.exit_room(&mut rooms_lock);
             user.current_room_id = Some(room_id.clone());
             rooms_lock.insert(room_id.clone(), room);
-            
-            let room_thread_id = room_id.clone();
-            let rooms_mutex = rooms.clone();
-            tokio::spawn(async move {
-                while let Some(msg) = rx.recv().await {
-                    let rooms = rooms_mutex.lock().await;
-                    let users = users_mutex.lock().await;
-                    let room = match rooms.get(&room_thread_id) {
-                        Some(room) => room,
-                        None => break,
-                    };
-
-                    room.broadcast_raw(users, msg);
+
+            tokio::spawn(run_turn_timer(
+                room_id.clone(),
+                timer_rx,
+                users_mutex.clone(),
+                rooms.clone(),
+            ));
+
+            tokio::spawn(run_room_broadcast(
+                room_id.clone(),
+                rx,
+                users_mutex.clone(),
+                rooms.clone(),
+            ));
+            if let Err(e) = user.tx.send(
+                RoomCreationReply {
+                    room_id: room_id.clone(),
                 }
-            });
-            if let Err(e) = user.tx.send(RoomCreationReply { room_id: room_id.clone() }.into_message()) {
+                .to_message(),
+            ) {
                 error!(
                     "While sending a room creation reply to {}: {}",
                     user.name, e
@@ -169,7 +302,7 @@ async fn handle_message(
                                 room_id,
                                 succeeded: false,
                             }
-                            .into_message(),
+                            .to_message(),
                         )
                         .unwrap();
                     return Ok(());
@@ -183,9 +316,14 @@ async fn handle_message(
                         room_id,
                         succeeded: true,
                     }
-                    .into_message(),
+                    .to_message(),
                 )
                 .unwrap();
+            // Spectators catch up with the battle they are joining
+            if let RoomBattleStatus::Started(battle) = &room.battle {
+                user.send(battle.snapshot(None)).unwrap();
+            }
+            room.send_info();
         }
         WsMessage::RoomExitRequest(_) => {
             let mut rooms = rooms.lock().await;
@@ -196,19 +334,77 @@ async fn handle_message(
                 send_request_error(&user.tx, "already_in_main_room").unwrap();
             }
         }
-        WsMessage::BattleStartRequest(req) => {
-            handle_battle_request(req, users, rooms.lock().await, username).await;
+        WsMessage::ListRulesetsRequest(_) => {
+            user.send(RulesetListReply {
+                rulesets: rulesets().into_iter().cloned().collect(),
+            })
+            .unwrap();
+        }
+        WsMessage::BattleStartRequest(BattleStartRequest {
+            other_user,
+            party,
+            team_preview,
+            format,
+            ruleset,
+        })
+        | WsMessage::BattleInviteRequest(BattleInviteRequest {
+            other_user,
+            party,
+            team_preview,
+            format,
+            ruleset,
+        }) if is_bot_name(&other_user) => {
+            handle_bot_battle_request(
+                BattleInviteRequest {
+                    other_user,
+                    party,
+                    team_preview,
+                    format,
+                    ruleset,
+                },
+                users,
+                rooms.lock().await,
+                username,
+                users_mutex.clone(),
+                rooms.clone(),
+            )
+            .await;
+        }
+        msg @ WsMessage::BattleStartRequest(_)
+        | msg @ WsMessage::BattleInviteRequest(_)
+        | msg @ WsMessage::BattleAcceptRequest(_)
+        | msg @ WsMessage::BattleDeclineRequest(_)
+        | msg @ WsMessage::CancelInviteRequest(_) => {
+            handle_invite_request(
+                msg,
+                users,
+                rooms.lock().await,
+                username,
+                (users_mutex.clone(), rooms.clone()),
+            )
+            .await;
+        }
+        WsMessage::TeamPreviewLockRequest(req) => {
+            handle_team_preview_request(req, users, rooms.lock().await, username).await;
         }
         msg @ WsMessage::UseMoveRequest(_) | msg @ WsMessage::SwitchRequest(_) => {
             handle_in_battle_request(msg, users, rooms.lock().await, username).await;
         }
+        WsMessage::BattleStateRequest(_) => {
+            handle_battle_state_request(users, rooms.lock().await, username).await;
+        }
+        msg @ WsMessage::ForfeitRequest(_)
+        | msg @ WsMessage::OfferDrawRequest(_)
+        | msg @ WsMessage::AcceptDrawRequest(_) => {
+            handle_battle_end_request(msg, users, rooms.lock().await, username).await;
+        }
         _ => {
             user.tx
                 .send(
                     RequestErrorMessage {
                         reason: "invalid_command".to_string(),
                     }
-                    .into_message(),
+                    .to_message(),
                 )
                 .unwrap();
         }
@@ -234,6 +430,69 @@ pub fn send_request_error(
         RequestErrorMessage {
             reason: String::from(error_str),
         }
-        .into_message(),
+        .to_message(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::time;
+
+    use super::*;
+    use crate::{
+        battle::timer::TimerSettings,
+        testing::{battle_room, ROOM_ID},
+    };
+
+    fn settings() -> TimerSettings {
+        TimerSettings {
+            reconnect_seconds: 30,
+            ..TimerSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn users_can_reconnect_to_their_battle() {
+        time::pause();
+        let mut room = battle_room(settings());
+        handle_connection_lost("second", room.users.clone(), room.rooms.clone()).await;
+        let events = room.received(None);
+        assert_eq!(events[0]["action"], "opponent_disconnected");
+        assert_eq!(events[0]["data"]["reconnect_seconds"], 30);
+
+        time::sleep(Duration::from_secs(20)).await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        reconnect_user(&mut *room.users.lock().await, &room.rooms, "second", tx).await;
+        let reply: serde_json::Value =
+            serde_json::from_str(rx.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(reply["action"], "room_join_status");
+        assert_eq!(room.received(None)[0]["action"], "opponent_reconnected");
+
+        // The reconnection window of the first connection is over by now
+        time::sleep(Duration::from_secs(20)).await;
+        assert!(room.users.lock().await["second"].disconnected_at.is_none());
+        let rooms = room.rooms.lock().await;
+        assert!(matches!(
+            rooms[ROOM_ID].battle,
+            RoomBattleStatus::Started(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn users_lose_if_they_do_not_reconnect_in_time() {
+        time::pause();
+        let mut room = battle_room(settings());
+        handle_connection_lost("second", room.users.clone(), room.rooms.clone()).await;
+        room.received(None);
+
+        time::sleep(Duration::from_secs(29)).await;
+        assert!(room.received(None).is_empty());
+        time::sleep(Duration::from_secs(2)).await;
+        let events = room.received(None);
+        assert_eq!(events[0]["action"], "battle_end");
+        assert_eq!(events[0]["data"]["winner"], "first");
+        assert_eq!(events[0]["data"]["reason"], "disconnected");
+        assert!(!room.users.lock().await.contains_key("second"));
+        assert_eq!(room.rooms.lock().await[ROOM_ID].users, ["first"]);
+    }
+}
@@ -2,54 +2,140 @@ use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 use pokemon_engine::{
+    battle::{Battlefield, Messenger},
     dragon::{BattleDragon, DragonData},
-    moves::{MoveStats, MoveTrait, SimpleDamagingMove},
+    moves::MoveTrait,
+    party::PartyId,
 };
 use serde::Deserialize;
 
 use crate::battle::ServerMessenger;
 
+use self::moves::MoveData;
+
 pub mod moves;
 
 lazy_static! {
     static ref DRAGONS: HashMap<String, DragonData> = load_dragons();
-    static ref SIMPLE_DAMAGING_MOVES: HashMap<String, SimpleMoveData> = load_simple_moves();
+    static ref SERVER_DRAGONS: HashMap<String, ServerDragonData> = load_server_dragons();
+    static ref MOVES: HashMap<String, MoveData> = load_moves();
+    static ref TYPE_CHART: HashMap<String, HashMap<String, f32>> = load_type_chart();
 }
 
 fn load_dragons() -> HashMap<String, DragonData> {
     serde_json::from_str(include_str!("data/dragons.json")).unwrap()
 }
 
+/// The parts of a dragon entry that only the server uses, read from the same
+/// file as the engine's [`DragonData`].
+#[derive(Deserialize)]
+pub struct ServerDragonData {
+    pub types: Vec<String>,
+    pub base_stats: ServerStats,
+    /// The moves the dragon can learn
+    pub moves: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ServerStats {
+    pub attack: u32,
+    pub defense: u32,
+    pub speed: u32,
+}
+
+fn load_server_dragons() -> HashMap<String, ServerDragonData> {
+    serde_json::from_str(include_str!("data/dragons.json")).unwrap()
+}
+
+pub fn server_dragon_data(name: &str) -> Option<&'static ServerDragonData> {
+    SERVER_DRAGONS.get(name)
+}
+
+/// The names of every dragon, in no particular order
+pub fn dragon_names() -> Vec<&'static String> {
+    SERVER_DRAGONS.keys().collect()
+}
+
+pub fn dragon_data(name: &str) -> Option<&'static DragonData> {
+    DRAGONS.get(name)
+}
+
 pub fn create_dragon(name: &str) -> Option<BattleDragon> {
     DRAGONS.get(name).map(|d| BattleDragon::new(d.base_stats))
 }
 
-#[derive(Deserialize)]
-struct SimpleMoveData {
-    base_power: u32,
-    name: String,
-    #[serde(default)]
-    crit_boost: u8,
+fn load_moves() -> HashMap<String, MoveData> {
+    serde_json::from_str(include_str!("data/moves.json")).unwrap()
+}
+
+pub fn move_data(move_name: &str) -> Option<&'static MoveData> {
+    MOVES.get(move_name)
+}
+
+/// The engine's representation of a damaging move. It deals the damage the
+/// server rolled with the battle's seeded generator, as the randomness of the
+/// engine's own moves can not be seeded.
+pub struct RolledMove {
+    damage: u32,
+}
+
+impl MoveTrait<ServerMessenger> for RolledMove {
+    fn make_move(&self, field: &mut Battlefield<ServerMessenger>, party: PartyId) {
+        let field: &Battlefield<ServerMessenger> = field;
+        field
+            .messenger
+            .on_damage(field, party.opposing(), self.damage);
+    }
 }
 
-fn load_simple_moves() -> HashMap<String, SimpleMoveData> {
-    serde_json::from_str(include_str!("data/simple_moves.json")).unwrap()
+/// Creates the engine's representation of a move hitting for `damage`. Moves
+/// without base power have no such representation.
+pub fn create_move(move_name: &str, damage: u32) -> Option<Box<dyn MoveTrait<ServerMessenger>>> {
+    move_data(move_name)?.base_power?;
+    Some(Box::new(RolledMove { damage }))
 }
 
-fn create_simple_move(name: &str) -> Option<SimpleDamagingMove> {
-    SIMPLE_DAMAGING_MOVES.get(name).map(
-        |SimpleMoveData {
-             base_power,
-             name,
-             crit_boost,
-         }| SimpleDamagingMove::new_crit(name.to_owned(), *base_power, *crit_boost),
-    )
+pub fn move_priority(move_name: &str) -> Option<i8> {
+    move_data(move_name).map(|m| m.priority)
 }
 
-pub fn create_move(move_name: &str) -> Option<Box<dyn MoveTrait<ServerMessenger>>> {
-    if let Some(simple_move) = create_simple_move(move_name) {
-        return Some(Box::new(simple_move));
+fn load_type_chart() -> HashMap<String, HashMap<String, f32>> {
+    serde_json::from_str(include_str!("data/types.json")).unwrap()
+}
+
+/// How effective a move of `move_type` is against a dragon with
+/// `defender_types`
+pub fn type_effectiveness(move_type: &str, defender_types: &[String]) -> f32 {
+    let row = match TYPE_CHART.get(move_type) {
+        Some(row) => row,
+        None => return 1.0,
+    };
+    defender_types
+        .iter()
+        .map(|t| row.get(t).copied().unwrap_or(1.0))
+        .product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| (*name).to_owned()).collect()
+    }
+
+    #[test]
+    fn type_effectiveness_multiplies_every_type() {
+        assert_eq!(type_effectiveness("fire", &types(&["air"])), 2.0);
+        assert_eq!(type_effectiveness("fire", &types(&["air", "poison"])), 4.0);
+        assert_eq!(type_effectiveness("fire", &types(&["air", "fire"])), 1.0);
+        assert_eq!(type_effectiveness("normal", &types(&["ghost"])), 0.0);
     }
 
-    None
+    #[test]
+    fn type_effectiveness_defaults_to_neutral() {
+        assert_eq!(type_effectiveness("electric", &types(&["normal"])), 1.0);
+        assert_eq!(type_effectiveness("unknown", &types(&["fire"])), 1.0);
+        assert_eq!(type_effectiveness("fire", &[]), 1.0);
+    }
 }
@@ -1 +1,177 @@
+use std::collections::BTreeMap;
 
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct MoveData {
+    /// User-friendly move name
+    pub name: String,
+    #[serde(rename = "type", default = "normal_type")]
+    pub move_type: String,
+    /// Moves without base power deal no damage, they only have effects
+    #[serde(default)]
+    pub base_power: Option<u32>,
+    #[serde(default = "certain")]
+    pub accuracy: u8,
+    /// Moves that never miss ignore accuracy and evasion
+    #[serde(default)]
+    pub never_misses: bool,
+    #[serde(default)]
+    pub priority: i8,
+    #[serde(default)]
+    pub crit_boost: u8,
+    #[serde(default)]
+    pub effects: Vec<MoveEffect>,
+    /// Two-turn moves charge in the first turn, and hit in the second one
+    #[serde(default)]
+    pub charge: Option<ChargeData>,
+    /// Spread moves hit every opposing dragon in doubles, dealing less damage
+    #[serde(default)]
+    pub spread: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ChargeData {
+    /// Whether the dragon can not be hit while charging
+    #[serde(default)]
+    pub semi_invulnerable: bool,
+}
+
+impl MoveData {
+    /// Whether the move has any effect on the enemy, so it can miss
+    pub fn targets_enemy(&self) -> bool {
+        self.base_power.is_some()
+            || self.effects.iter().any(|effect| match effect {
+                MoveEffect::StatStages { target, .. } | MoveEffect::Status { target, .. } => {
+                    *target == EffectTarget::Enemy
+                }
+                _ => false,
+            })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MoveEffect {
+    StatStages {
+        target: EffectTarget,
+        stages: BTreeMap<Stat, i8>,
+        #[serde(default = "certain")]
+        chance: u8,
+    },
+    Status {
+        target: EffectTarget,
+        status: StatusKind,
+        #[serde(default = "certain")]
+        chance: u8,
+    },
+    /// The user loses `percent`% of the damage it dealt
+    Recoil {
+        percent: u8,
+    },
+    /// The user regains `percent`% of its maximum HP
+    Heal {
+        percent: u8,
+    },
+    Weather {
+        weather: Weather,
+        turns: u8,
+    },
+    /// Places a hazard on the side of the enemy
+    Hazard {
+        hazard: Hazard,
+    },
+    /// The moves of the user never miss until it is switched out
+    SureHit,
+    /// Removes every hazard from the side of the target
+    ClearHazards {
+        target: EffectTarget,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectTarget {
+    User,
+    Enemy,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Stat {
+    Attack,
+    Defense,
+    Speed,
+    Accuracy,
+    Evasion,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusKind {
+    Poison,
+    Burn,
+    Paralysis,
+    Sleep,
+    Flinch,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Weather {
+    ElectricStorm,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Hazard {
+    ScorchingStones,
+}
+
+impl Stat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Attack => "attack",
+            Self::Defense => "defense",
+            Self::Speed => "speed",
+            Self::Accuracy => "accuracy",
+            Self::Evasion => "evasion",
+        }
+    }
+}
+
+impl StatusKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Poison => "poison",
+            Self::Burn => "burn",
+            Self::Paralysis => "paralysis",
+            Self::Sleep => "sleep",
+            Self::Flinch => "flinch",
+        }
+    }
+}
+
+impl Weather {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ElectricStorm => "electric_storm",
+        }
+    }
+}
+
+impl Hazard {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ScorchingStones => "scorching_stones",
+        }
+    }
+}
+
+fn normal_type() -> String {
+    "normal".to_owned()
+}
+
+fn certain() -> u8 {
+    100
+}
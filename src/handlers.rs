@@ -2,7 +2,10 @@ use rand::Rng;
 
 use warp::{reply::json, Rejection, Reply};
 
-use crate::messages::{self, *};
+use crate::{
+    messages::{self, *},
+    replay::load_replay,
+};
 
 pub async fn health_check() -> Result<impl Reply, Rejection> {
     Ok(json(&messages::HealthReply { code: 200 }))
@@ -13,7 +16,12 @@ pub async fn register_room() -> Result<impl Reply, Rejection> {
         .sample_iter(&crate::UppercaseAlphanumericDistribution::new())
         .take(5)
         .collect();
-    Ok(json(
-        &messages::RoomCreationReply { room_id }.into_jsonable(),
-    ))
+    Ok(json(&messages::RoomCreationReply { room_id }.to_jsonable()))
+}
+
+pub async fn get_replay(id: String) -> Result<impl Reply, Rejection> {
+    match load_replay(&id).await {
+        Some(replay) => Ok(json(&replay.to_jsonable())),
+        None => Err(warp::reject::not_found()),
+    }
 }
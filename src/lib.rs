@@ -0,0 +1,33 @@
+use rand::distributions::Uniform;
+
+pub mod battle;
+pub mod bot;
+pub mod communication;
+pub mod data;
+pub mod error;
+pub mod handlers;
+pub mod messages;
+pub mod replay;
+pub mod room;
+pub mod user;
+
+#[cfg(test)]
+mod testing;
+
+pub struct UppercaseAlphanumericDistribution(Uniform<usize>);
+impl UppercaseAlphanumericDistribution {
+    pub fn new() -> Self {
+        Self(Uniform::new(0, 36))
+    }
+}
+impl Default for UppercaseAlphanumericDistribution {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl rand::distributions::Distribution<char> for UppercaseAlphanumericDistribution {
+    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> char {
+        let slice: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890";
+        slice.chars().nth(rng.sample(self.0)).unwrap()
+    }
+}
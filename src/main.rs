@@ -1,34 +1,9 @@
 use std::{collections::HashMap, sync::Arc};
 
-use rand::distributions::Uniform;
-use room::Rooms;
+use pokemon_ws_server::{communication, handlers, room::Rooms, user::Users};
 use tokio::sync::Mutex;
 use warp::{ws::Ws, Filter};
 
-use crate::user::Users;
-
-mod battle;
-mod communication;
-mod data;
-mod error;
-mod handlers;
-mod messages;
-mod room;
-mod user;
-
-pub struct UppercaseAlphanumericDistribution(Uniform<usize>);
-impl UppercaseAlphanumericDistribution {
-    pub fn new() -> Self {
-        Self(Uniform::new(0, 36))
-    }
-}
-impl rand::distributions::Distribution<char> for UppercaseAlphanumericDistribution {
-    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> char {
-        let slice: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890";
-        slice.chars().nth(rng.sample(self.0)).unwrap()
-    }
-}
-
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
@@ -52,10 +27,20 @@ async fn main() {
             ws.on_upgrade(move |ws| communication::ws_handler(ws, name, users, rooms))
         });
     let register_room_endpoint = warp::path("register-room").and_then(handlers::register_room);
-
-    warp::serve(health_endpoint.or(echo_endpoint).or(register_room_endpoint))
-        .run(([0, 0, 0, 0], 8000))
-        .await;
+    let replay_endpoint = warp::get()
+        .and(warp::path("replays"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and_then(handlers::get_replay);
+
+    warp::serve(
+        health_endpoint
+            .or(echo_endpoint)
+            .or(register_room_endpoint)
+            .or(replay_endpoint),
+    )
+    .run(([0, 0, 0, 0], 8000))
+    .await;
 }
 
 // fn with_broadcast_channels<T: Send>(
@@ -1,8 +1,10 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json::{from_value, json};
 use warp::ws::Message;
 
-use crate::error::StringError;
+use crate::{battle::ruleset::Ruleset, error::StringError};
 
 macro_rules! server_reply {
     ($name:ident $json_name:expr => { $($field_name:ident : $field_type:ty),* $(,)? }) => {
@@ -99,45 +101,283 @@ json_structs! {
         room_id: String,
         succeeded: bool,
     }
+    reply RoomInfo RoomInfoNotify "room_info" => {
+        users: Vec<String>,
+        spectators: u32,
+    }
     message RoomExitRequest RoomExitRequest "leave_room" => {}
 
+    message ListRulesetsRequest ListRulesetsRequest "list_rulesets" => {}
+    reply RulesetList RulesetListReply "ruleset_list" => {
+        rulesets: Vec<Ruleset>,
+    }
+
     message BattleStartRequest BattleStartRequest "start_battle" => {
         other_user: String,
-        dragon_names: Vec<String>,
+        party: Vec<PartySlot>,
+        team_preview: Option<TeamPreviewOptions>,
+        format: Option<String>,
+        ruleset: Option<String>,
     }
 
-    reply BattleInvitation BattleInvitation "battle_invite" => {
-        other_user: String
+    message BattleInviteRequest BattleInviteRequest "battle_invite" => {
+        other_user: String,
+        party: Vec<PartySlot>,
+        team_preview: Option<TeamPreviewOptions>,
+        format: Option<String>,
+        ruleset: Option<String>,
+    }
+    message BattleAcceptRequest BattleAcceptRequest "battle_accept" => {
+        other_user: String,
+        party: Vec<PartySlot>,
+    }
+    message BattleDeclineRequest BattleDeclineRequest "battle_decline" => {
+        other_user: String,
+    }
+    message CancelInviteRequest CancelInviteRequest "battle_cancel_invite" => {
+        other_user: String,
+    }
+
+    reply BattleInvitation BattleInvitation "battle_invitation" => {
+        other_user: String,
+        format: String,
+        ruleset: String,
+        expires_in: u64,
+    }
+    reply BattleInviteSent BattleInviteSent "battle_invite_sent" => {
+        other_user: String,
+        expires_in: u64,
+    }
+    reply BattleInviteEnded BattleInviteEnded "battle_invite_ended" => {
+        from: String,
+        to: String,
+        reason: String,
+    }
+
+    reply TeamPreviewNotify TeamPreviewNotify "team_preview" => {
+        other_user: String,
+        other_party: Vec<String>,
+        bring: u8,
+        expires_in: u64,
+    }
+    message TeamPreviewLockRequest TeamPreviewLockRequest "team_preview_lock" => {
+        dragons: Vec<u8>,
+    }
+    reply TeamLockedNotify TeamLockedNotify "team_preview_locked" => {
+        username: String,
     }
 
     reply BattleStartNotify BattleStartNotify "battle_start" => {
         other_party: Vec<String>,
+        format: String,
     }
 
     message UseMoveRequest UseMoveRequest "battle_use_move" => {
         move_name: String,
+        slot: Option<u8>,
+        target: Option<u8>,
     }
 
     message SwitchRequest SwitchRequest "battle_switch" => {
         next_dragon: u8,
+        slot: Option<u8>,
     }
 
     reply UseMoveNotify UseMoveNotify "use_move_notify" => {
         party: u8,
+        slot: u8,
+        move_name: String,
+    }
+
+    reply ChargeNotify ChargeNotify "battle_charge_notify" => {
+        party: u8,
+        slot: u8,
         move_name: String,
+        semi_invulnerable: bool,
+    }
+
+    reply MoveMissedNotify MoveMissedNotify "battle_move_missed" => {
+        party: u8,
+        slot: u8,
+        move_name: String,
+        reason: String,
     }
 
     reply DamageNotify DamageNotify "battle_damage_notify" => {
         party: u8,
+        slot: u8,
         amount: u32,
         fainted: bool,
+        effectiveness: Option<f32>,
     }
 
     reply SwitchNotify SwitchNotify "battle_switch_notify" => {
         party: u8,
+        slot: u8,
         next_idx: u8,
+        dragon: String,
         switch_allowed: bool
     }
+
+    reply HealNotify HealNotify "battle_heal_notify" => {
+        party: u8,
+        slot: u8,
+        amount: u32,
+    }
+
+    reply StatChangeNotify StatChangeNotify "battle_stat_change" => {
+        party: u8,
+        slot: u8,
+        stat: String,
+        change: i8,
+        stage: i8,
+    }
+
+    reply WeatherChangeNotify WeatherChangeNotify "battle_weather_change" => {
+        weather: Option<String>,
+        turns_left: u8,
+    }
+
+    reply HazardChangeNotify HazardChangeNotify "battle_hazard_change" => {
+        party: u8,
+        hazard: String,
+        present: bool,
+    }
+
+    reply EffectNotify EffectNotify "battle_effect_notify" => {
+        party: u8,
+        slot: u8,
+        effect: String,
+    }
+
+    reply BattleRequestSwitch BattleRequestSwitch "battle_request_switch" => {
+        slot: u8,
+        legal_indexes: Vec<u8>,
+    }
+
+    reply BattleEnd BattleEndNotify "battle_end" => {
+        winner: Option<String>,
+        loser: Option<String>,
+        reason: String,
+        replay_id: String,
+        summary: BattleSummary,
+    }
+
+    message ForfeitRequest ForfeitRequest "battle_forfeit" => {}
+
+    message OfferDrawRequest OfferDrawRequest "battle_offer_draw" => {}
+
+    message AcceptDrawRequest AcceptDrawRequest "battle_accept_draw" => {}
+
+    reply DrawOfferNotify DrawOfferNotify "battle_draw_offered" => {
+        username: String,
+    }
+
+    reply OpponentDisconnected OpponentDisconnectedNotify "opponent_disconnected" => {
+        username: String,
+        reconnect_seconds: u64,
+    }
+
+    reply OpponentReconnected OpponentReconnectedNotify "opponent_reconnected" => {
+        username: String,
+    }
+
+    message BattleStateRequest BattleStateRequest "get_battle_state" => {}
+
+    reply BattleState BattleStateReply "battle_state" => {
+        format: String,
+        turn: u32,
+        party: Option<u8>,
+        parties: Vec<PartySnapshot>,
+        weather: Option<String>,
+        weather_turns_left: u8,
+    }
+
+    reply BattleTurnStart BattleTurnStart "battle_turn_start" => {
+        turn: u32,
+        deadline: u64,
+        waiting_for: Vec<String>,
+    }
+
+    reply BattleTurnWarning BattleTurnWarning "battle_turn_warning" => {
+        seconds_left: u64,
+        waiting_for: Vec<String>,
+    }
+
+    reply BattleTurnTimeout BattleTurnTimeout "battle_turn_timeout" => {
+        username: String,
+        timeouts: u8,
+        max_timeouts: u8,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PartySlot {
+    pub dragon: String,
+    pub moves: Vec<String>,
+    /// The level of the dragon, which scales its stats. Dragons are at the
+    /// highest level if it is not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+/// Settings of the team preview of a battle, which only happens if they are
+/// sent with the invitation
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TeamPreviewOptions {
+    /// How many dragons each user brings to the battle, all of them if not
+    /// set
+    #[serde(default)]
+    pub bring: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BattleSummary {
+    pub turns: u32,
+    pub parties: Vec<PartySummary>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PartySummary {
+    pub username: String,
+    pub dragons: Vec<DragonSummary>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DragonSummary {
+    pub name: String,
+    pub hp: u32,
+    pub max_hp: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PartySnapshot {
+    pub username: String,
+    /// The dragon in the first slot
+    pub active: u8,
+    /// The dragon in each slot, which is only one in singles
+    pub active_slots: Vec<u8>,
+    /// Whether the server is waiting for the party to choose an action
+    pub waiting: bool,
+    /// Hazards on the side of the party
+    pub hazards: Vec<String>,
+    pub dragons: Vec<DragonSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DragonSnapshot {
+    /// The position of the dragon in its party
+    pub index: u8,
+    pub name: String,
+    pub hp: u32,
+    pub max_hp: u32,
+    pub status: Option<String>,
+    /// Stat stages that are not zero
+    pub stages: BTreeMap<String, i8>,
+    /// The two-turn move the dragon is charging
+    pub charging: Option<String>,
+    /// Only known by the owner of the dragon
+    pub moves: Option<Vec<String>>,
 }
 
 #[derive(Serialize)]
@@ -147,10 +387,10 @@ pub struct HealthReply {
 
 pub trait WsSentMessage: Serialize + Sized {
     fn get_type() -> &'static str;
-    fn into_message(&self) -> Message {
+    fn to_message(&self) -> Message {
         Message::text(json!({"action": Self::get_type(), "data": self}).to_string())
     }
-    fn into_jsonable(&self) -> ProtocolMessage {
+    fn to_jsonable(&self) -> ProtocolMessage {
         ProtocolMessage {
             action: Self::get_type().to_string(),
             data: serde_json::json!(self),
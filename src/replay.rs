@@ -0,0 +1,155 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use log::{error, info};
+use pokemon_engine::party::PartyId;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use warp::ws::Message;
+
+use crate::{
+    battle::{format::BattleFormat, BattleAction},
+    messages::{PartySlot, WsSentMessage},
+};
+
+/// Where finished battles are saved, one JSON file per replay
+const REPLAY_DIRECTORY: &str = "replays";
+
+/// Everything needed to play a battle back: what went in, and what came out
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub id: String,
+    /// The seed of the random number generator of the battle
+    pub seed: u64,
+    /// Replays saved before doubles battles are singles
+    #[serde(default = "singles")]
+    pub format: String,
+    pub parties: Vec<ReplayParty>,
+    /// The actions played, in the order they were played
+    pub actions: Vec<ReplayAction>,
+    /// Every message sent to the room during the battle, in the
+    /// `{"action", "data"}` format
+    pub events: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayParty {
+    pub username: String,
+    pub party: Vec<PartySlot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReplayAction {
+    pub turn: u32,
+    pub party: u8,
+    /// The client message choosing the action
+    pub action: String,
+    pub data: serde_json::Value,
+    /// Whether the dragon was locked into the action, like the second turn
+    /// of a two-turn move, instead of the user choosing it
+    #[serde(default)]
+    pub locked: bool,
+}
+
+impl WsSentMessage for Replay {
+    fn get_type() -> &'static str {
+        "replay"
+    }
+}
+
+pub type SharedReplay = Arc<Mutex<Replay>>;
+
+impl Replay {
+    pub fn new(id: String, seed: u64, format: BattleFormat, parties: Vec<ReplayParty>) -> Self {
+        Self {
+            id,
+            seed,
+            format: format.name().to_owned(),
+            parties,
+            actions: vec![],
+            events: vec![],
+        }
+    }
+
+    pub fn record_action(
+        &mut self,
+        turn: u32,
+        party_id: PartyId,
+        slot: u8,
+        action: &BattleAction,
+        locked: bool,
+    ) {
+        let (action, data) = match action {
+            BattleAction::UseMove(move_name, target) => (
+                "battle_use_move",
+                json!({ "move_name": move_name, "slot": slot, "target": target }),
+            ),
+            BattleAction::Switch(next_dragon) => (
+                "battle_switch",
+                json!({ "next_dragon": next_dragon, "slot": slot }),
+            ),
+        };
+        self.actions.push(ReplayAction {
+            turn,
+            party: party_id.into(),
+            action: action.to_owned(),
+            data,
+            locked,
+        });
+    }
+}
+
+fn singles() -> String {
+    BattleFormat::Singles.name().to_owned()
+}
+
+/// Replay IDs are generated by the server, so anything else can not be one
+fn replay_path(id: &str) -> Option<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(PathBuf::from(REPLAY_DIRECTORY).join(format!("{}.json", id)))
+}
+
+/// Forwards the events of a battle to its room, recording them in the
+/// replay. Once the battle is over and every sender of `events` is dropped,
+/// the replay is saved.
+pub async fn record_battle(
+    mut events: UnboundedReceiver<Message>,
+    room_tx: UnboundedSender<Message>,
+    replay: SharedReplay,
+) {
+    while let Some(event) = events.recv().await {
+        if let Some(value) = event
+            .to_str()
+            .ok()
+            .and_then(|text| serde_json::from_str(text).ok())
+        {
+            replay.lock().unwrap().events.push(value);
+        }
+        // The room might have been removed since
+        let _ = room_tx.send(event);
+    }
+
+    let (id, contents) = {
+        let replay = replay.lock().unwrap();
+        (replay.id.clone(), serde_json::to_string(&*replay).unwrap())
+    };
+    let path = replay_path(&id).unwrap();
+    let saved = match tokio::fs::create_dir_all(REPLAY_DIRECTORY).await {
+        Ok(()) => tokio::fs::write(&path, contents).await,
+        Err(e) => Err(e),
+    };
+    match saved {
+        Ok(()) => info!("Saved replay {}", id),
+        Err(e) => error!("While saving replay {}: {}", id, e),
+    }
+}
+
+pub async fn load_replay(id: &str) -> Option<Replay> {
+    let contents = tokio::fs::read(replay_path(id)?).await.ok()?;
+    serde_json::from_slice(&contents).ok()
+}
@@ -2,37 +2,81 @@ use std::{collections::HashMap, ops::Deref, sync::Arc};
 
 use pokemon_engine::battle::{Battlefield, NopMessenger};
 
-use tokio::sync::{Mutex, mpsc::UnboundedSender};
+use tokio::sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    Mutex,
+};
 use warp::ws::Message;
 
-use crate::{battle::RoomBattleStatus, messages::WsSentMessage, user::User};
+use crate::{
+    battle::{invite::Invite, timer::TimerCommand, RoomBattleStatus},
+    messages::{RoomInfoNotify, WsSentMessage},
+    user::{User, Users},
+};
 
 pub struct Room {
     pub users: Vec<String>,
     pub battle: RoomBattleStatus,
+    /// The battle invitations waiting for an answer
+    pub invites: Vec<Invite>,
     pub tx: UnboundedSender<Message>,
+    /// Controls the turn timer task of the room
+    pub timer: UnboundedSender<TimerCommand>,
 }
 
 pub type Rooms = Arc<Mutex<HashMap<String, Room>>>;
 
 impl Room {
-    pub fn new(initial_user: String, tx: UnboundedSender<Message>) -> Self {
+    pub fn new(
+        initial_user: String,
+        tx: UnboundedSender<Message>,
+        timer: UnboundedSender<TimerCommand>,
+    ) -> Self {
         Self {
             users: vec![initial_user],
             battle: RoomBattleStatus::None,
+            invites: vec![],
             tx,
+            timer,
         }
     }
 
+    /// The users watching the ongoing battle of the room without taking part
+    /// in it
+    pub fn spectators(&self) -> Vec<&String> {
+        match &self.battle {
+            RoomBattleStatus::Started(battle) => self
+                .users
+                .iter()
+                .filter(|user| battle.user_party_id(user).is_none())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Tells everyone in the room who is in it
+    pub fn send_info(&self) {
+        self.tx
+            .send(
+                RoomInfoNotify {
+                    users: self.users.clone(),
+                    spectators: self.spectators().len() as u32,
+                }
+                .to_message(),
+            )
+            .unwrap();
+    }
+
     pub fn broadcast<U, M: WsSentMessage>(&self, users: U, message: M)
     where
         U: Deref<Target = HashMap<String, User>>,
     {
-        self.broadcast_raw(users, message.into_message());
+        self.broadcast_raw(users, message.to_message());
     }
 
     pub fn broadcast_raw<U>(&self, users: U, message: Message)
-    where U: Deref<Target = HashMap<String, User>>,
+    where
+        U: Deref<Target = HashMap<String, User>>,
     {
         for user in self.users.iter() {
             let user = &users[user];
@@ -41,6 +85,25 @@ impl Room {
     }
 }
 
+/// Sends the messages of a room to everyone in it, until the room is removed
+pub async fn run_room_broadcast(
+    room_id: String,
+    mut messages: UnboundedReceiver<Message>,
+    users: Users,
+    rooms: Rooms,
+) {
+    while let Some(msg) = messages.recv().await {
+        let users = users.lock().await;
+        let rooms = rooms.lock().await;
+        let room = match rooms.get(&room_id) {
+            Some(room) => room,
+            None => break,
+        };
+
+        room.broadcast_raw(users, msg);
+    }
+}
+
 // impl Room {
 //     pub fn new() -> Self {
 //         let (tx, rx) = mpsc::unbounded_channel();
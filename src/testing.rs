@@ -0,0 +1,147 @@
+//! Fixtures shared by the unit tests
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde_json::Value;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver},
+    Mutex,
+};
+use warp::ws::Message;
+
+use crate::{
+    battle::{
+        format::BattleFormat,
+        timer::{run_turn_timer, TimerSettings},
+        Battle, RoomBattleStatus,
+    },
+    messages::PartySlot,
+    room::{run_room_broadcast, Room, Rooms},
+    user::{User, Users},
+};
+
+/// The room of [`test_room`]
+pub const ROOM_ID: &str = "TEST";
+
+/// A party slot of a dragon at the default level
+pub fn party_slot(dragon: &str, moves: &[&str]) -> PartySlot {
+    PartySlot {
+        dragon: dragon.to_owned(),
+        moves: moves.iter().map(|m| (*m).to_owned()).collect(),
+        level: None,
+    }
+}
+
+/// Two valid parties of two dragons
+pub fn parties() -> (Vec<PartySlot>, Vec<PartySlot>) {
+    (
+        vec![
+            party_slot("mew", &["harapas", "lava_bomba", "mereg", "fantom"]),
+            party_slot("mewtwo", &["villamcsapas", "tuzcsova", "sortuz"]),
+        ],
+        vec![
+            party_slot("mewtwo", &["foldrenges", "marcangolas", "sose_hibazik"]),
+            party_slot("mew", &["fejeles", "perzseles", "szelloket"]),
+        ],
+    )
+}
+
+/// A room of users connected to the test instead of a socket
+pub struct TestRoom {
+    pub users: Users,
+    pub rooms: Rooms,
+    /// The events of the battle the room started with, if any
+    pub events: UnboundedReceiver<Message>,
+    /// What is sent to each user
+    pub inboxes: HashMap<String, UnboundedReceiver<Message>>,
+}
+
+impl TestRoom {
+    /// The actions of the messages sent to a user, or of the battle events
+    /// with `None`, since the last call
+    pub fn received(&mut self, inbox: Option<&str>) -> Vec<Value> {
+        let rx = match inbox {
+            Some(name) => self.inboxes.get_mut(name).unwrap(),
+            None => &mut self.events,
+        };
+        let mut messages = vec![];
+        while let Ok(message) = rx.try_recv() {
+            messages.push(serde_json::from_str(message.to_str().unwrap()).unwrap());
+        }
+        messages
+    }
+}
+
+/// Sets up a room of `usernames` with its broadcast and turn timer tasks
+pub fn test_room(usernames: &[&str], battle: RoomBattleStatus) -> TestRoom {
+    let mut inboxes = HashMap::new();
+    let mut users = HashMap::new();
+    for name in usernames {
+        let (tx, rx) = mpsc::unbounded_channel();
+        users.insert(
+            (*name).to_owned(),
+            User {
+                name: (*name).to_owned(),
+                tx,
+                current_room_id: Some(ROOM_ID.to_owned()),
+                disconnected_at: None,
+            },
+        );
+        inboxes.insert((*name).to_owned(), rx);
+    }
+
+    let (room_tx, room_rx) = mpsc::unbounded_channel();
+    let (timer_tx, timer_rx) = mpsc::unbounded_channel();
+    let mut room = Room::new(usernames[0].to_owned(), room_tx, timer_tx);
+    room.users = usernames.iter().map(|name| (*name).to_owned()).collect();
+    room.battle = battle;
+
+    let users = Arc::new(Mutex::new(users));
+    let rooms = Arc::new(Mutex::new(
+        vec![(ROOM_ID.to_owned(), room)].into_iter().collect(),
+    ));
+    tokio::spawn(run_room_broadcast(
+        ROOM_ID.to_owned(),
+        room_rx,
+        users.clone(),
+        rooms.clone(),
+    ));
+    tokio::spawn(run_turn_timer(
+        ROOM_ID.to_owned(),
+        timer_rx,
+        users.clone(),
+        rooms.clone(),
+    ));
+    let (_, events) = mpsc::unbounded_channel();
+    TestRoom {
+        users,
+        rooms,
+        events,
+        inboxes,
+    }
+}
+
+/// Sets up a room where `first` and `second` battle each other. The battle
+/// is not started, so nothing is waiting for actions yet.
+pub fn battle_room(settings: TimerSettings) -> TestRoom {
+    let usernames = ("first".to_owned(), "second".to_owned());
+    let (party1, party2) = parties();
+    let (events_tx, events) = mpsc::unbounded_channel();
+    let mut battle = Battle::new(
+        usernames,
+        (&party1, &party2),
+        BattleFormat::Singles,
+        events_tx,
+        ROOM_ID.to_owned(),
+        0,
+    )
+    .unwrap();
+    battle.timer_settings = settings;
+    TestRoom {
+        events,
+        ..test_room(
+            &["first", "second"],
+            RoomBattleStatus::Started(Box::new(battle)),
+        )
+    }
+}
@@ -4,24 +4,30 @@ use std::{
     sync::Arc,
 };
 
-use tokio::sync::{
-    mpsc::{self, error::SendError},
-    Mutex,
+use tokio::{
+    sync::{
+        mpsc::{self, error::SendError},
+        Mutex,
+    },
+    time::Instant,
 };
 use warp::ws::Message;
 
-use crate::{messages::*, room::Room};
+use crate::{battle::handle_user_left, messages::*, room::Room};
 
 #[derive(Clone)]
 pub struct User {
     pub name: String,
     pub tx: mpsc::UnboundedSender<Message>,
     pub current_room_id: Option<String>,
+    /// When the user lost their connection, if they are waiting to reconnect
+    /// to their battle
+    pub disconnected_at: Option<Instant>,
 }
 
 impl User {
     pub fn send<T: WsSentMessage>(&self, msg: T) -> Result<(), SendError<Message>> {
-        self.tx.send(msg.into_message())
+        self.tx.send(msg.to_message())
     }
 
     pub fn send_raw(&self, message: Message) -> Result<(), SendError<Message>> {
@@ -35,17 +41,27 @@ impl User {
     }
 
     pub fn exit_room<T>(&mut self, rooms: &mut T) -> Option<()>
+    where
+        T: DerefMut + Deref<Target = HashMap<String, Room>>,
+    {
+        self.exit_room_with_reason(rooms, "left_room")
+    }
+
+    /// Exits the current room, ending the user's battle with `reason`
+    pub fn exit_room_with_reason<T>(&mut self, rooms: &mut T, reason: &str) -> Option<()>
     where
         T: DerefMut + Deref<Target = HashMap<String, Room>>,
     {
         let current_room_id = self.current_room_id.as_ref()?;
         let room = rooms.get_mut(current_room_id).unwrap();
+        handle_user_left(room, &self.name, reason);
         if room.users.len() == 1 {
             rooms.remove(current_room_id);
             return Some(());
         }
         let idx = room.users.iter().position(|s| s == &self.name).unwrap();
         room.users.remove(idx);
+        room.send_info();
         Some(())
     }
 }
@@ -0,0 +1,81 @@
+//! Runs the simulator binary the way it is used for balancing
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+const PARTY1: &str = r#"[
+    {"dragon": "mew", "moves": ["harapas", "lava_bomba", "mereg", "fantom"]},
+    {"dragon": "mewtwo", "moves": ["villamcsapas", "tuzcsova"]}
+]"#;
+const PARTY2: &str = r#"[
+    {"dragon": "mewtwo", "moves": ["foldrenges", "gombvillam", "tombolas"]},
+    {"dragon": "mew", "moves": ["fejeles", "perzseles"]}
+]"#;
+
+/// Writes a party file for one test, so that tests running at the same time do
+/// not share them
+fn party_file(test: &str, name: &str, party: &str) -> String {
+    let path: PathBuf = env::temp_dir().join(format!(
+        "simulate-{}-{}-{}.json",
+        std::process::id(),
+        test,
+        name
+    ));
+    fs::write(&path, party).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+fn simulate(args: &[&str]) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_simulate"))
+        .args(args)
+        .output()
+        .unwrap();
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout).unwrap())
+    } else {
+        Err(String::from_utf8(output.stderr).unwrap())
+    }
+}
+
+#[test]
+fn reports_are_deterministic_for_a_seed() {
+    let party1 = party_file("deterministic", "1", PARTY1);
+    let party2 = party_file("deterministic", "2", PARTY2);
+    for format in &["singles", "doubles"] {
+        let args = [
+            party1.as_str(),
+            party2.as_str(),
+            "--battles",
+            "50",
+            "--seed",
+            "42",
+            "--strategy1",
+            "greedy",
+            "--strategy2",
+            "lookahead",
+            "--battle-format",
+            format,
+        ];
+        let report = simulate(&args).unwrap();
+        assert_eq!(report, simulate(&args).unwrap());
+
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(report["battles"], 50);
+        let wins: u64 = report["parties"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|party| party["wins"].as_u64().unwrap())
+            .sum();
+        let draws = report["draws"].as_u64().unwrap();
+        let unfinished = report["unfinished"].as_u64().unwrap();
+        assert_eq!(wins + draws + unfinished, 50);
+    }
+}
+
+#[test]
+fn invalid_parties_are_rejected() {
+    let party1 = party_file("invalid", "1", PARTY1);
+    let party2 = party_file("invalid", "2", r#"[{"dragon": "mew", "moves": []}]"#);
+    let error = simulate(&[&party1, &party2]).unwrap_err();
+    assert!(error.contains("invalid party: no_moves"), "{}", error);
+}